@@ -0,0 +1,84 @@
+use clap::Parser;
+use halo2_base::{utils::ScalarField, AssignedValue, Context};
+use halo2_scaffold::gadget::{
+    distance::{DistanceChip, DistanceInstructions},
+    fixed_point::FixedPointChip,
+    fixed_point_vec::FixedPointVectorInstructions,
+    vectordb::{CommitmentParams, VectorDBChip, VectorDBInstructions},
+};
+use halo2_scaffold::scaffold::{cmd::Cli, run};
+use serde::{Deserialize, Serialize};
+use std::env::var;
+
+const PARAMS: CommitmentParams<3, 2> = CommitmentParams::DEFAULT;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CircuitInput {
+    pub queries: Vec<Vec<f64>>,
+    pub database: Vec<Vec<f64>>,
+}
+
+fn batch_query<F: ScalarField>(
+    ctx: &mut Context<F>,
+    input: CircuitInput,
+    make_public: &mut Vec<AssignedValue<F>>,
+) {
+    let dim = input.database[0].len();
+    assert!(input.database.iter().chain(input.queries.iter()).all(|vec| vec.len() == dim));
+
+    let lookup_bits =
+        var("LOOKUP_BITS").unwrap_or_else(|_| panic!("LOOKUP_BITS not set")).parse().unwrap();
+    const PRECISION_BITS: u32 = 48;
+    let fixed_point_chip = FixedPointChip::<F, PRECISION_BITS>::default(lookup_bits);
+    let distance_chip = DistanceChip::default(&fixed_point_chip);
+    let vectordb_chip = VectorDBChip::default(&fixed_point_chip);
+    let mut poseidon_chip = PARAMS.poseidon_chip(ctx);
+
+    let queries: Vec<Vec<AssignedValue<F>>> = input
+        .queries
+        .iter()
+        .map(|q| ctx.assign_witnesses(fixed_point_chip.quantize_vector(&q)))
+        .collect();
+    let database: Vec<Vec<AssignedValue<F>>> = input
+        .database
+        .iter()
+        .map(|v| ctx.assign_witnesses(fixed_point_chip.quantize_vector(&v)))
+        .collect();
+
+    // norms of the database are computed once, and shared by all queries
+    let batch = vectordb_chip.batch_nearest_vector(
+        ctx,
+        &mut poseidon_chip,
+        PARAMS.scheme,
+        &queries,
+        &database,
+        &|ctx, v| distance_chip.norm(ctx, v),
+        &|ctx, v, v_norm, q, q_norm| {
+            distance_chip.cosine_distance_with_norms(ctx, v, q, v_norm, q_norm)
+        },
+    );
+
+    for (i, result) in batch.results.iter().enumerate() {
+        make_public.extend(result.iter());
+
+        println!("Result #{}:", i);
+        for e in result {
+            let val = fixed_point_chip.dequantization(*e.value());
+            print!("{:?} ", val);
+        }
+        println!("");
+    }
+
+    make_public.push(batch.database_root);
+    println!("Merkle Root: {:?}", batch.database_root.value());
+
+    println!("#queries: {:?}", queries.len());
+    println!("#vectors: {:?}", database.len());
+}
+
+fn main() {
+    env_logger::init();
+
+    let args = Cli::parse();
+    run(batch_query, args);
+}
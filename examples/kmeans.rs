@@ -44,9 +44,10 @@ fn kmeans<F: ScalarField>(
         .map(|v| ctx.assign_witnesses(fixed_point_chip.quantize_vector(&v)))
         .collect();
 
-    let (centroids, _) = vectordb_chip
+    let result = vectordb_chip
         // FIXME: until I can solve the bug with euclidean, we are not using Euclidean distance...
         .kmeans::<K, I>(ctx, &vectors, &|ctx, a, b| distance_chip.cosine_distance(ctx, a, b));
+    let centroids = result.centroids;
 
     // output centroids as public variables
     centroids.iter().for_each(|c| {
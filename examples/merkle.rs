@@ -4,18 +4,15 @@ use halo2_scaffold::{
     gadget::{
         fixed_point::FixedPointChip,
         fixed_point_vec::FixedPointVectorInstructions,
-        vectordb::{VectorDBChip, VectorDBInstructions},
+        vectordb::{CommitmentParams, VectorDBChip, VectorDBInstructions},
     },
+    native::merkle::MerkleTree,
     scaffold::{cmd::Cli, run},
 };
-use poseidon::PoseidonChip;
 use serde::{Deserialize, Serialize};
 use std::env::var;
 
-const T: usize = 3;
-const RATE: usize = 2;
-const R_F: usize = 8;
-const R_P: usize = 57;
+const PARAMS: CommitmentParams<3, 2> = CommitmentParams::DEFAULT;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct CircuitInput {
@@ -34,7 +31,7 @@ fn merkle_poseidon<F: ScalarField>(
     const PRECISION_BITS: u32 = 32;
     let fixed_point_chip = FixedPointChip::<F, PRECISION_BITS>::default(lookup_bits);
     let vectordb_chip = VectorDBChip::default(&fixed_point_chip);
-    let mut poseidon_chip = PoseidonChip::<F, T, RATE>::new(ctx, R_F, R_P).unwrap();
+    let mut poseidon_chip = PARAMS.poseidon_chip(ctx);
 
     let database: Vec<Vec<AssignedValue<F>>> = input
         .vectors
@@ -44,6 +41,12 @@ fn merkle_poseidon<F: ScalarField>(
 
     let root = vectordb_chip.merkle_commitment(ctx, &mut poseidon_chip, &database);
 
+    // the same root can be computed natively, without a circuit
+    let qvectors: Vec<Vec<F>> =
+        input.vectors.iter().map(|v| fixed_point_chip.quantize_vector(&v)).collect();
+    let native_root = MerkleTree::new(&qvectors, &PARAMS).root();
+    assert_eq!(*root.value(), native_root, "native root differs from the circuit root");
+
     make_public.push(root);
     println!("Merkle root: {:?}", root.value());
 }
@@ -10,11 +10,10 @@ use halo2_scaffold::gadget::{
     distance::{DistanceChip, DistanceInstructions},
     fixed_point::FixedPointChip,
     fixed_point_vec::FixedPointVectorInstructions,
-    vectordb::{VectorDBChip, VectorDBInstructions},
+    vectordb::{CommitmentParams, ResultVisibility, VectorDBChip, VectorDBInstructions},
 };
 use halo2_scaffold::scaffold::cmd::Cli;
 use halo2_scaffold::scaffold::run;
-use poseidon::PoseidonChip;
 use serde::{Deserialize, Serialize};
 use std::env::var;
 
@@ -22,12 +21,15 @@ use std::env::var;
 pub struct CircuitInput {
     pub query: Vec<f64>,
     pub database: Vec<Vec<f64>>,
+    /// Blinding salt of the query, if given the commitment to the query is made public.
+    #[serde(default)]
+    pub salt: Option<u128>,
+    /// Whether only the salted hash of the result is made public, which requires a salt.
+    #[serde(default)]
+    pub hash_result: bool,
 }
 
-const T: usize = 3;
-const RATE: usize = 2;
-const R_F: usize = 8;
-const R_P: usize = 57;
+const PARAMS: CommitmentParams<3, 2> = CommitmentParams::DEFAULT;
 
 fn exhaustive_merkle<F: ScalarField>(
     ctx: &mut Context<F>,
@@ -42,7 +44,7 @@ fn exhaustive_merkle<F: ScalarField>(
     let fixed_point_chip = FixedPointChip::<F, PRECISION_BITS>::default(lookup_bits);
     let distance_chip = DistanceChip::default(&fixed_point_chip);
     let vectordb_chip = VectorDBChip::default(&fixed_point_chip);
-    let mut poseidon_chip = PoseidonChip::<F, T, RATE>::new(ctx, R_F, R_P).unwrap();
+    let mut poseidon_chip = PARAMS.poseidon_chip(ctx);
 
     let query: Vec<AssignedValue<F>> =
         ctx.assign_witnesses(fixed_point_chip.quantize_vector(&input.query));
@@ -55,7 +57,27 @@ fn exhaustive_merkle<F: ScalarField>(
     let (_, result) = vectordb_chip.nearest_vector(ctx, &query, &database, &|ctx, a, b| {
         distance_chip.cosine_distance(ctx, a, b)
     });
-    make_public.extend(result.iter());
+
+    // commit to the query with its salt, so that the client can later prove that the
+    // result corresponds to their query without revealing it to the verifier
+    match input.salt {
+        Some(salt) => {
+            let salt = ctx.load_witness(F::from_u128(salt));
+            let commitment = vectordb_chip.commit_query(ctx, &mut poseidon_chip, &query, salt);
+            println!("Query Commitment: {:?}", commitment.value());
+
+            let visibility =
+                if input.hash_result { ResultVisibility::Hashed } else { ResultVisibility::Raw };
+            let public_result =
+                vectordb_chip.public_result(ctx, &mut poseidon_chip, visibility, &result, salt);
+            make_public.extend(public_result);
+            make_public.push(commitment);
+        }
+        None => {
+            assert!(!input.hash_result, "hashing the result requires a salt");
+            make_public.extend(result.iter());
+        }
+    }
 
     println!("Result:");
     for e in result {
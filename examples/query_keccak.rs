@@ -0,0 +1,89 @@
+use axiom_eth::{keccak::KeccakChip, EthChip};
+use clap::Parser;
+use halo2_base::{
+    gates::GateInstructions, halo2_proofs::halo2curves::bn256::Fr, utils::ScalarField,
+    AssignedValue, Context, QuantumCell::Constant,
+};
+use halo2_scaffold::gadget::{
+    distance::{DistanceChip, DistanceInstructions},
+    fixed_point::{FixedPointChip, FixedPointInstructions},
+    fixed_point_vec::FixedPointVectorInstructions,
+    vectordb::{VectorDBChip, VectorDBInstructions},
+};
+use halo2_scaffold::scaffold::{cmd::Cli, run_eth};
+use serde::{Deserialize, Serialize};
+use std::env::var;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CircuitInput {
+    pub query: Vec<f64>,
+    pub database: Vec<Vec<f64>>,
+}
+
+fn exhaustive_keccak(
+    ctx: &mut Context<Fr>,
+    _: &EthChip<Fr>,
+    keccak: &mut KeccakChip<Fr>,
+    input: CircuitInput,
+    make_public: &mut Vec<AssignedValue<Fr>>,
+) -> impl FnOnce(&mut Context<Fr>, &mut Context<Fr>, &EthChip<Fr>) + Clone {
+    assert!(input.database.iter().all(|vec| vec.len() == input.query.len()));
+
+    // set by `run_eth`, defaults to the lookup bits of the Keccak circuit
+    let lookup_bits =
+        var("LOOKUP_BITS").unwrap_or_else(|_| panic!("LOOKUP_BITS not set")).parse().unwrap();
+    const PRECISION_BITS: u32 = 48;
+    let fixed_point_chip = FixedPointChip::<Fr, PRECISION_BITS>::default(lookup_bits);
+    let distance_chip = DistanceChip::default(&fixed_point_chip);
+    let vectordb_chip = VectorDBChip::default(&fixed_point_chip);
+
+    let query: Vec<AssignedValue<Fr>> =
+        ctx.assign_witnesses(fixed_point_chip.quantize_vector(&input.query));
+    let database: Vec<Vec<AssignedValue<Fr>>> = input
+        .database
+        .iter()
+        .map(|v| ctx.assign_witnesses(fixed_point_chip.quantize_vector(&v)))
+        .collect();
+
+    let (_, result) = vectordb_chip.nearest_vector(ctx, &query, &database, &|ctx, a, b| {
+        distance_chip.cosine_distance(ctx, a, b)
+    });
+    make_public.extend(result.iter());
+
+    println!("Result:");
+    for e in result {
+        let val = fixed_point_chip.dequantization(*e.value());
+        print!("{:?} ", val);
+    }
+    println!("");
+
+    // compute the keccak commitment to the database, and expose it as two 128-bit
+    // halves (most significant first) since the root does not fit in a field element
+    let root = vectordb_chip.keccak_merkle_commitment(ctx, keccak, &database);
+    let gate = fixed_point_chip.gate();
+    for half in root.chunks(16) {
+        let mut acc = ctx.load_zero();
+        for byte in half {
+            acc = gate.mul_add(ctx, acc, Constant(Fr::from(256)), *byte);
+        }
+        make_public.push(acc);
+    }
+
+    print!("Keccak Root: 0x");
+    for byte in root.iter() {
+        print!("{:02x}", byte.value().get_lower_32());
+    }
+    println!("");
+
+    println!("#vectors: {:?}", database.len());
+
+    // nothing to do in the second phase, where the keccak computations are constrained
+    |_: &mut Context<Fr>, _: &mut Context<Fr>, _: &EthChip<Fr>| {}
+}
+
+fn main() {
+    env_logger::init();
+
+    let args = Cli::parse();
+    run_eth(exhaustive_keccak, args);
+}
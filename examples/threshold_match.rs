@@ -0,0 +1,76 @@
+use clap::Parser;
+use halo2_base::{utils::ScalarField, AssignedValue, Context};
+use halo2_scaffold::gadget::{
+    distance::{DistanceChip, DistanceInstructions},
+    fixed_point::FixedPointChip,
+    fixed_point_vec::FixedPointVectorInstructions,
+    vectordb::{CommitmentParams, VectorDBChip, VectorDBInstructions},
+};
+use halo2_scaffold::scaffold::{cmd::Cli, run};
+use serde::{Deserialize, Serialize};
+use std::env::var;
+
+const PARAMS: CommitmentParams<3, 2> = CommitmentParams::BINARY;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CircuitInput {
+    pub query: Vec<f64>,
+    pub database: Vec<Vec<f64>>,
+    pub threshold: f64,
+    /// Blinding salt of the query commitment, which should be random.
+    pub salt: u128,
+}
+
+fn threshold_match<F: ScalarField>(
+    ctx: &mut Context<F>,
+    input: CircuitInput,
+    make_public: &mut Vec<AssignedValue<F>>,
+) {
+    assert!(input.database.iter().all(|vec| vec.len() == input.query.len()));
+
+    let lookup_bits =
+        var("LOOKUP_BITS").unwrap_or_else(|_| panic!("LOOKUP_BITS not set")).parse().unwrap();
+    const PRECISION_BITS: u32 = 48;
+    let fixed_point_chip = FixedPointChip::<F, PRECISION_BITS>::default(lookup_bits);
+    let distance_chip = DistanceChip::default(&fixed_point_chip);
+    let vectordb_chip = VectorDBChip::default(&fixed_point_chip);
+    let mut poseidon_chip = PARAMS.poseidon_chip(ctx);
+
+    // the query & the database are private, while the threshold is part of the circuit
+    let query: Vec<AssignedValue<F>> =
+        ctx.assign_witnesses(fixed_point_chip.quantize_vector(&input.query));
+    let database: Vec<Vec<AssignedValue<F>>> = input
+        .database
+        .iter()
+        .map(|v| ctx.assign_witnesses(fixed_point_chip.quantize_vector(&v)))
+        .collect();
+    let threshold = ctx.load_constant(fixed_point_chip.quantization(input.threshold));
+    let salt = ctx.load_witness(F::from_u128(input.salt));
+
+    let result = vectordb_chip.threshold_match(
+        ctx,
+        &mut poseidon_chip,
+        PARAMS.scheme,
+        &query,
+        salt,
+        &database,
+        threshold,
+        &|ctx, a, b| distance_chip.euclidean_distance(ctx, a, b),
+    );
+
+    // only the root, the query commitment & the boolean are revealed
+    make_public.push(result.database_root);
+    make_public.push(result.query_commitment);
+    make_public.push(result.matched);
+
+    println!("Database Root: {:?}", result.database_root.value());
+    println!("Query Commitment: {:?}", result.query_commitment.value());
+    println!("Matched: {:?}", *result.matched.value() == F::one());
+}
+
+fn main() {
+    env_logger::init();
+
+    let args = Cli::parse();
+    run(threshold_match, args);
+}
@@ -79,6 +79,24 @@ pub trait DistanceInstructions<F: ScalarField, const PRECISION_BITS: u32> {
     ) -> AssignedValue<F>
     where
         F: ScalarField;
+
+    /// Computes the Euclidean norm (L2) of a quantized vector.
+    fn norm(&self, ctx: &mut Context<F>, a: &Vec<AssignedValue<F>>) -> AssignedValue<F>
+    where
+        F: ScalarField;
+
+    /// Same as `cosine_distance`, but with the norms of the vectors given, so that they can
+    /// be computed once for a vector that is compared many times.
+    fn cosine_distance_with_norms(
+        &self,
+        ctx: &mut Context<F>,
+        a: &Vec<AssignedValue<F>>,
+        b: &Vec<AssignedValue<F>>,
+        a_norm: AssignedValue<F>,
+        b_norm: AssignedValue<F>,
+    ) -> AssignedValue<F>
+    where
+        F: ScalarField;
 }
 
 impl<'a, F: ScalarField, const PRECISION_BITS: u32> DistanceInstructions<F, PRECISION_BITS>
@@ -129,14 +147,36 @@ impl<'a, F: ScalarField, const PRECISION_BITS: u32> DistanceInstructions<F, PREC
     {
         assert_eq!(a.len(), b.len());
 
-        let ab: AssignedValue<F> = self.fixed_point_gate.inner_product(ctx, a.clone(), b.clone()); // sum (a.b)
+        let a_norm = self.norm(ctx, a);
+        let b_norm = self.norm(ctx, b);
+
+        self.cosine_distance_with_norms(ctx, a, b, a_norm, b_norm)
+    }
+
+    fn norm(&self, ctx: &mut Context<F>, a: &Vec<AssignedValue<F>>) -> AssignedValue<F>
+    where
+        F: ScalarField,
+    {
         let aa = self.fixed_point_gate.inner_product(ctx, a.clone(), a.clone()); // sum (a^2)
-        let bb = self.fixed_point_gate.inner_product(ctx, b.clone(), b.clone()); // sum (b^2)
+        self.fixed_point_gate.qsqrt(ctx, aa)
+    }
 
-        let aa_sqrt = self.fixed_point_gate.qsqrt(ctx, aa);
-        let bb_sqrt = self.fixed_point_gate.qsqrt(ctx, bb);
+    fn cosine_distance_with_norms(
+        &self,
+        ctx: &mut Context<F>,
+        a: &Vec<AssignedValue<F>>,
+        b: &Vec<AssignedValue<F>>,
+        a_norm: AssignedValue<F>,
+        b_norm: AssignedValue<F>,
+    ) -> AssignedValue<F>
+    where
+        F: ScalarField,
+    {
+        assert_eq!(a.len(), b.len());
+
+        let ab: AssignedValue<F> = self.fixed_point_gate.inner_product(ctx, a.clone(), b.clone()); // sum (a.b)
 
-        let denom = self.fixed_point_gate.qmul(ctx, aa_sqrt, bb_sqrt);
+        let denom = self.fixed_point_gate.qmul(ctx, a_norm, b_norm);
         let sim = self.fixed_point_gate.qdiv(ctx, ab, denom);
 
         let one = ctx.load_constant(self.fixed_point_gate.quantization(1.0));
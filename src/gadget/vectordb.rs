@@ -1,4 +1,10 @@
-use halo2_base::{gates::GateInstructions, utils::ScalarField, AssignedValue, Context};
+use axiom_eth::keccak::KeccakChip;
+use halo2_base::{
+    gates::GateInstructions,
+    utils::ScalarField,
+    AssignedValue, Context,
+    QuantumCell::{Constant, Existing},
+};
 use poseidon::PoseidonChip;
 use std::fmt::Debug;
 
@@ -9,74 +15,2689 @@ pub enum VectorDBStrategy {
     Vertical,
 }
 
+/// Version of the Merkle commitment, see [`VectorDBInstructions::merkle_commitment_with_scheme`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CommitmentScheme {
+    /// Leaves are the hashes of the vectors, padding leaves are zeros and each internal
+    /// node is the hash of its children.
+    Plain,
+    /// Leaves, padding leaves and internal nodes are hashed with distinct domain tags, and
+    /// the root is the hash of the tree root along with the number of vectors & their dimension.
+    DomainSeparated,
+}
+
+impl CommitmentScheme {
+    /// Domain tag prepended to each vector when hashing a leaf.
+    pub const LEAF_TAG: u64 = 1;
+    /// Domain tag prepended to the children when hashing an internal node.
+    pub const NODE_TAG: u64 = 2;
+    /// Domain tag that is hashed on its own for a padding leaf.
+    pub const PADDING_TAG: u64 = 3;
+    /// Domain tag prepended to the tree root, the number of vectors & the dimension.
+    pub const ROOT_TAG: u64 = 4;
+    /// Domain tag that is hashed on its own for the leaf of a deleted vector, in all schemes.
+    pub const TOMBSTONE_TAG: u64 = 5;
+    /// Domain tag prepended to the metadata fields of an entry.
+    pub const METADATA_TAG: u64 = 6;
+    /// Domain tag prepended to the id, vector hash & metadata hash of an entry in a sparse Merkle tree.
+    pub const ENTRY_TAG: u64 = 7;
+    /// Domain tag prepended to the vector hash & metadata hash of a leaf with metadata.
+    pub const RECORD_TAG: u64 = 8;
+    /// Domain tag prepended to a query vector when hashing it.
+    pub const QUERY_TAG: u64 = 9;
+    /// Domain tag prepended to a result along with its salt when hashing it.
+    pub const RESULT_TAG: u64 = 10;
+}
+
+/// How a result is made public, see [`VectorDBInstructions::public_result`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ResultVisibility {
+    /// The result is public as is.
+    Raw,
+    /// Only the salted hash of the result is public, which the client can open later.
+    Hashed,
+}
+
+/// Parameters of Merkle commitments, i.e. the Poseidon instance (state width `T`, rate `RATE`
+/// which is also the arity of the tree, full rounds `r_f` and partial rounds `r_p`) along with
+/// the commitment scheme.
+///
+/// Commitments computed with different parameters never match, so the chip, the native trees
+/// and the binaries should all use the same parameters, e.g. [`CommitmentParams::DEFAULT`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CommitmentParams<const T: usize, const RATE: usize> {
+    pub r_f: usize,
+    pub r_p: usize,
+    pub scheme: CommitmentScheme,
+}
+
+impl CommitmentParams<3, 2> {
+    /// Binary tree with the original (plain) scheme, as used by `merkle_commitment`.
+    pub const DEFAULT: Self = Self::new(8, 57, CommitmentScheme::Plain);
+    /// Binary tree with the domain-separated scheme.
+    pub const BINARY: Self = Self::new(8, 57, CommitmentScheme::DomainSeparated);
+}
+
+impl CommitmentParams<5, 4> {
+    /// 4-ary tree with the domain-separated scheme, which has half the levels of a binary tree.
+    pub const QUATERNARY: Self = Self::new(8, 60, CommitmentScheme::DomainSeparated);
+}
+
+impl<const T: usize, const RATE: usize> CommitmentParams<T, RATE> {
+    /// Version of the layout of the identifier, see `id`.
+    pub const ID_VERSION: u64 = 1;
+
+    pub const fn new(r_f: usize, r_p: usize, scheme: CommitmentScheme) -> Self {
+        Self { r_f, r_p, scheme }
+    }
+
+    /// Identifier of the parameters, which can be embedded in committed metadata (e.g. as
+    /// `F::from(params.id())`) so that a root can't be mistaken for one with other parameters.
+    ///
+    /// From the most significant byte: the version of the layout, `T`, `RATE`, `r_f`, `r_p`
+    /// (two bytes) and the scheme (two bytes).
+    pub fn id(&self) -> u64 {
+        assert!(T < 256 && RATE < 256 && self.r_f < 256 && self.r_p < 65536);
+        let scheme: u64 = match self.scheme {
+            CommitmentScheme::Plain => 0,
+            CommitmentScheme::DomainSeparated => 1,
+        };
+
+        (Self::ID_VERSION << 56)
+            | ((T as u64) << 48)
+            | ((RATE as u64) << 40)
+            | ((self.r_f as u64) << 32)
+            | ((self.r_p as u64) << 16)
+            | scheme
+    }
+
+    /// Creates a Poseidon chip with these parameters.
+    pub fn poseidon_chip<F: ScalarField>(&self, ctx: &mut Context<F>) -> PoseidonChip<F, T, RATE> {
+        PoseidonChip::new(ctx, self.r_f, self.r_p).unwrap()
+    }
+}
+
+/// Kind of a provable database update, see [`VectorDBInstructions::merkle_update`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MerkleUpdate {
+    /// Inserts a vector into the first empty slot, i.e. the one at index `len`.
+    Insert,
+    /// Replaces the vector at an index with another one.
+    Replace,
+    /// Deletes the vector at an index by replacing it with a tombstone, so that the
+    /// indices of the other vectors stay the same.
+    Delete,
+}
+
+/// Output of the K-means algorithm, see [`VectorDBInstructions::kmeans`].
+#[derive(Clone, Debug)]
+pub struct KMeansResult<F: ScalarField, const K: usize> {
+    /// Centroids after the final iteration.
+    pub centroids: [Vec<AssignedValue<F>>; K],
+    /// Quantized cluster indicators for each vector, i.e. a quantized one
+    /// at the index of the cluster and zero on all other places.
+    pub cluster_indicators: Vec<[AssignedValue<F>; K]>,
+    /// Boolean cluster indicators for each vector, i.e. a field one at the index
+    /// of the cluster and zero on all other places. These can be used as selectors.
+    pub cluster_selectors: Vec<[AssignedValue<F>; K]>,
+    /// Cluster id for each vector, as a field element in `[0, K)`.
+    pub cluster_ids: Vec<AssignedValue<F>>,
+}
+
+/// Convergence report of the K-means algorithm, see [`VectorDBInstructions::kmeans_with_convergence`].
+#[derive(Clone, Debug)]
+pub struct KMeansConvergence<F: ScalarField> {
+    /// K-means objective (i.e. inertia), the sum of squared distances of each vector
+    /// to its assigned centroid.
+    pub objective: AssignedValue<F>,
+    /// Movement of the centroids in the final iteration, the sum of squared Euclidean
+    /// distances between each centroid before and after the final update.
+    pub movement: AssignedValue<F>,
+}
+
+/// Output of the hierarchical K-means algorithm, see [`VectorDBInstructions::hierarchical_kmeans`].
+///
+/// The centroids form a tree with branching factor `B`, where level `l` has `B^(l + 1)` centroids
+/// and the children of the `i`-th centroid at level `l` are at indices `[i * B, (i + 1) * B)` at
+/// level `l + 1`.
+#[derive(Clone, Debug)]
+pub struct HierarchicalKMeansResult<F: ScalarField> {
+    /// Centroids at each level of the tree.
+    pub levels: Vec<Vec<Vec<AssignedValue<F>>>>,
+    /// Merkle root over the centroids at each level of the tree.
+    pub level_roots: Vec<AssignedValue<F>>,
+    /// Boolean indicators over the leaf clusters (i.e. the last level) for each vector.
+    pub cluster_selectors: Vec<Vec<AssignedValue<F>>>,
+    /// Leaf cluster id for each vector, as a field element in `[0, B^depth)`.
+    pub cluster_ids: Vec<AssignedValue<F>>,
+}
+
+/// Output of the K-medoids algorithm, see [`VectorDBInstructions::kmedoids`].
+#[derive(Clone, Debug)]
+pub struct KMedoidsResult<F: ScalarField, const K: usize> {
+    /// Medoids after the final iteration, each of them is one of the input vectors.
+    pub medoids: [Vec<AssignedValue<F>>; K],
+    /// Index of each medoid within the input vectors, as a field element.
+    pub medoid_indices: [AssignedValue<F>; K],
+    /// Boolean indicator into the input vectors for each medoid (one-hot encoded).
+    pub medoid_indicators: [Vec<AssignedValue<F>>; K],
+    /// Boolean cluster indicators for each vector (one-hot encoded).
+    pub cluster_selectors: Vec<[AssignedValue<F>; K]>,
+    /// Cluster id for each vector, as a field element in `[0, K)`.
+    pub cluster_ids: Vec<AssignedValue<F>>,
+}
+
+/// An entry found by a search, see [`VectorDBInstructions::top_k`].
+#[derive(Clone, Debug)]
+pub struct SearchResult<F: ScalarField> {
+    /// Distance of the entry to the query.
+    pub distance: AssignedValue<F>,
+    /// Boolean indicator into the database, which is 1 at the index of the entry.
+    pub indicator: Vec<AssignedValue<F>>,
+    /// Vector of the entry.
+    pub vector: Vec<AssignedValue<F>>,
+    /// Metadata of the entry, selected with the same indicator as the vector.
+    pub metadata: Vec<AssignedValue<F>>,
+}
+
+/// Output of a range query, see [`VectorDBInstructions::range_query`].
+#[derive(Clone, Debug)]
+pub struct RangeQueryResult<F: ScalarField> {
+    /// Boolean membership for each vector, which is 1 if it is within the threshold.
+    pub members: Vec<AssignedValue<F>>,
+    /// Number of vectors within the threshold.
+    pub count: AssignedValue<F>,
+    /// Merkle root over the matching vectors, where the leaves of the others are padding.
+    pub commitment: AssignedValue<F>,
+}
+
+/// Output of a two-level (IVF) query, see [`VectorDBInstructions::ivf_query`].
+#[derive(Clone, Debug)]
+pub struct IvfQueryResult<F: ScalarField> {
+    /// Merkle root over the centroids, where each leaf also binds the root of its cluster.
+    pub ivf_root: AssignedValue<F>,
+    /// Boolean indicator into the centroids, which is 1 at the nearest centroid.
+    pub centroid_indicator: Vec<AssignedValue<F>>,
+    /// Merkle root of the selected cluster.
+    pub cluster_root: AssignedValue<F>,
+    /// Boolean indicator into the selected cluster, which is 1 at the index of the result.
+    pub indicator: Vec<AssignedValue<F>>,
+    /// The closest vector within the selected cluster.
+    pub result: Vec<AssignedValue<F>>,
+}
+
+/// Output of a multi-probe IVF query, see [`VectorDBInstructions::ivf_query_multi_probe`].
+#[derive(Clone, Debug)]
+pub struct IvfMultiProbeResult<F: ScalarField, const P: usize> {
+    /// Merkle root over the centroids, where each leaf also binds the root of its cluster.
+    pub ivf_root: AssignedValue<F>,
+    /// Boolean indicators into the centroids, one for each of the `P` nearest centroids
+    /// in increasing order of distance.
+    pub centroid_indicators: [Vec<AssignedValue<F>>; P],
+    /// Merkle roots of the probed clusters, in the same order.
+    pub cluster_roots: [AssignedValue<F>; P],
+    /// Boolean indicator into the union of the probed clusters (concatenated in the same order),
+    /// which is 1 at the index of the result.
+    pub indicator: Vec<AssignedValue<F>>,
+    /// The closest vector within the probed clusters.
+    pub result: Vec<AssignedValue<F>>,
+}
+
+/// Output of a batch of queries, see [`VectorDBInstructions::batch_nearest_vector`].
+#[derive(Clone, Debug)]
+pub struct BatchSearchResult<F: ScalarField> {
+    /// Merkle root over the database, computed once for all queries.
+    pub database_root: AssignedValue<F>,
+    /// Boolean indicator into the database for each query, which is 1 at the index of its result.
+    pub indicators: Vec<Vec<AssignedValue<F>>>,
+    /// The closest vector for each query.
+    pub results: Vec<Vec<AssignedValue<F>>>,
+}
+
+/// Output of a private threshold match, see [`VectorDBInstructions::threshold_match`].
+#[derive(Clone, Debug)]
+pub struct ThresholdMatch<F: ScalarField> {
+    /// Merkle root over the database.
+    pub database_root: AssignedValue<F>,
+    /// Salted commitment to the query vector, see [`VectorDBInstructions::commit_query`].
+    pub query_commitment: AssignedValue<F>,
+    /// Boolean that is 1 if some vector is within the threshold of the query.
+    pub matched: AssignedValue<F>,
+}
+
+/// Comparison of an attribute against a value, see [`Condition`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    LessThan,
+    LessOrEqual,
+    GreaterThan,
+    GreaterOrEqual,
+}
+
+/// A condition on the attributes of a vector, i.e. `attributes[field] <comparison> value`,
+/// see [`VectorDBInstructions::filtered_nearest_vector`].
+///
+/// Attributes are compared as signed values (e.g. quantized or plain integers) within the
+/// valid range of the `FixedPointChip`.
+#[derive(Clone, Debug)]
+pub struct Condition<F: ScalarField> {
+    /// Index of the attribute that is compared.
+    pub field: usize,
+    pub comparison: Comparison,
+    pub value: AssignedValue<F>,
+}
+
+/// Commitments of an index built with K-means, see [`VectorDBInstructions::index_commitment`].
+#[derive(Clone, Debug)]
+pub struct IndexCommitment<F: ScalarField> {
+    /// Merkle root over the database.
+    pub database_root: AssignedValue<F>,
+    /// Merkle root over the centroids.
+    pub centroids_root: AssignedValue<F>,
+    /// Merkle root over the vectors of each cluster, zero for an empty cluster.
+    pub cluster_roots: Vec<AssignedValue<F>>,
+}
+
 #[derive(Clone, Debug)]
 pub struct VectorDBChip<'a, F: ScalarField, const PRECISION_BITS: u32> {
     strategy: VectorDBStrategy,
     pub fixed_point_gate: &'a FixedPointChip<F, PRECISION_BITS>,
 }
 
-impl<'a, F: ScalarField, const PRECISION_BITS: u32> VectorDBChip<'a, F, PRECISION_BITS> {
-    pub fn new(
-        strategy: VectorDBStrategy,
-        fixed_point_gate: &'a FixedPointChip<F, PRECISION_BITS>,
-    ) -> Self {
-        Self { strategy, fixed_point_gate }
-    }
+impl<'a, F: ScalarField, const PRECISION_BITS: u32> VectorDBChip<'a, F, PRECISION_BITS> {
+    pub fn new(
+        strategy: VectorDBStrategy,
+        fixed_point_gate: &'a FixedPointChip<F, PRECISION_BITS>,
+    ) -> Self {
+        Self { strategy, fixed_point_gate }
+    }
+
+    pub fn default(fixed_point_gate: &'a FixedPointChip<F, PRECISION_BITS>) -> Self {
+        Self::new(VectorDBStrategy::Vertical, fixed_point_gate)
+    }
+
+    /// A large quantized value that is used to mask out candidates when looking for a
+    /// minimum. It is much larger than any distance (or sum of distances) of interest,
+    /// while being small enough for the fixed-point comparisons. Where a masked candidate
+    /// must never win, the unmasked values are constrained with `assert_below_sentinel`.
+    fn sentinel(&self) -> F {
+        self.fixed_point_gate.quantization(2f64.powi(PRECISION_BITS as i32 - 1))
+    }
+
+    /// Constrains the quantized `value` to be less than the sentinel, so that a candidate
+    /// masked to the sentinel can never tie with (or beat) an unmasked one.
+    fn assert_below_sentinel(&self, ctx: &mut Context<F>, value: AssignedValue<F>) {
+        let gate = self.fixed_point_gate.gate();
+        let diff = gate.sub(ctx, value, Constant(self.sentinel()));
+        let is_below = self.fixed_point_gate.is_neg(ctx, diff);
+        gate.assert_is_const(ctx, &is_below, &F::one());
+    }
+
+    /// Finds the minimum among `values`, and returns it along with an indicator
+    /// that is 1 at the first index of the minimum, and 0 on all other places.
+    ///
+    /// Only the first occurrence is marked, so the indicator is one-hot even when
+    /// there are ties.
+    fn argmin(
+        &self,
+        ctx: &mut Context<F>,
+        values: &[AssignedValue<F>],
+    ) -> (AssignedValue<F>, Vec<AssignedValue<F>>) {
+        let gate = self.fixed_point_gate.gate();
+
+        let min: AssignedValue<F> = values
+            .iter()
+            .copied()
+            .reduce(|min, v| self.fixed_point_gate.qmin(ctx, min, v))
+            .unwrap();
+
+        // `found` becomes 1 once the minimum is seen, and stays so afterwards
+        let mut found = ctx.load_zero();
+        let indicator: Vec<AssignedValue<F>> = values
+            .iter()
+            .map(|v| {
+                let is_min = gate.is_equal(ctx, min, *v);
+                let is_first = gate.mul_not(ctx, found, is_min);
+                found = gate.add(ctx, found, is_first);
+                is_first
+            })
+            .collect();
+
+        (min, indicator)
+    }
+
+    /// Returns a boolean mask over `capacity` slots that is 1 exactly at the first `len` ones,
+    /// which also constrains `len` to be at most `capacity`.
+    fn length_mask(
+        &self,
+        ctx: &mut Context<F>,
+        len: AssignedValue<F>,
+        capacity: usize,
+    ) -> Vec<AssignedValue<F>> {
+        let gate = self.fixed_point_gate.gate();
+
+        let len_value = len.value().get_lower_128();
+        let mask = ctx.assign_witnesses(
+            (0..capacity).map(|i| F::from(((i as u128) < len_value) as u64)).collect::<Vec<F>>(),
+        );
+
+        // the mask must be bits that never go from 0 to 1, i.e. a prefix of ones,
+        // and their sum must be the length
+        let mut previous = ctx.load_constant(F::one());
+        for m in mask.iter() {
+            gate.assert_bit(ctx, *m);
+            let both = gate.and(ctx, *m, previous);
+            ctx.constrain_equal(&both, m);
+            previous = *m;
+        }
+        let count = gate.sum(ctx, mask.clone());
+        ctx.constrain_equal(&count, &len);
+
+        mask
+    }
+
+    /// Assigns each vector to its closest centroid, returning a boolean
+    /// one-hot indicator over the centroids for each vector.
+    fn assign_clusters<const K: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        vectors: &Vec<Vec<AssignedValue<F>>>,
+        centroids: &[Vec<AssignedValue<F>>; K],
+        distance: &dyn Fn(
+            &mut Context<F>,
+            &Vec<AssignedValue<F>>,
+            &Vec<AssignedValue<F>>,
+        ) -> AssignedValue<F>,
+    ) -> Vec<[AssignedValue<F>; K]> {
+        // instead of assigning a cluster id to each vector,
+        // we will store an indicator (one-hot encoding) for that cluster
+        // suppose K = 4 and vectors A and B belong to 1, 3 respectively
+        // we would have [0, 1, 0, 0] and [0, 0, 0, 1] as the indicators.
+        vectors
+            .iter()
+            .map(|v| {
+                // compute distance to centroids
+                let distances: Vec<AssignedValue<F>> =
+                    centroids.iter().map(|c| distance(ctx, c, v)).collect();
+
+                let (_, selectors) = self.argmin(ctx, &distances);
+                selectors.try_into().unwrap()
+            })
+            .collect()
+    }
+
+    /// Computes the mean vector of each cluster, w.r.t. the boolean cluster selectors.
+    ///
+    /// An empty cluster keeps its previous centroid. Its mean would be a division by zero,
+    /// which has no valid witness, so `kmeans` could not be proven as soon as a cluster went
+    /// empty (e.g. with duplicate initial centroids, or masked vectors in hierarchical K-means).
+    fn update_centroids<const K: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        vectors: &Vec<Vec<AssignedValue<F>>>,
+        cluster_selectors: &Vec<[AssignedValue<F>; K]>,
+        previous_centroids: &[Vec<AssignedValue<F>>; K],
+    ) -> [Vec<AssignedValue<F>>; K] {
+        let gate = self.fixed_point_gate.gate();
+        let scale = Constant(self.fixed_point_gate.quantization_scale);
+
+        let mut centroids: [Vec<AssignedValue<F>>; K] = [(); K].map(|_| vec![]);
+        for cluster_id in 0..K {
+            // the selectors for this cluster indicate whether a vector belongs to it or not
+            let is_in_cluster: Vec<AssignedValue<F>> =
+                cluster_selectors.iter().map(|selectors| selectors[cluster_id]).collect();
+
+            // number of vectors in this cluster, quantized; for an empty cluster we divide
+            // by one instead, and the result is discarded anyways
+            let cluster_size = gate.sum(ctx, is_in_cluster.clone());
+            let is_empty = gate.is_zero(ctx, cluster_size);
+            let cluster_size = gate.select(ctx, Constant(F::one()), cluster_size, is_empty);
+            let cluster_size = gate.mul(ctx, cluster_size, scale);
+
+            // sum the vectors in this cluster by taking an inner product with the selectors,
+            // this is fine because `v` is already quantized, and the selectors are bits so that
+            // we can stay away from a quantized multiplication here
+            centroids[cluster_id] = (0..vectors[0].len())
+                .map(|i| {
+                    let sum = gate.inner_product(
+                        ctx,
+                        vectors.iter().map(|v| v[i]),
+                        is_in_cluster.iter().map(|s| Existing(*s)),
+                    );
+
+                    // divide by cluster size to get the mean
+                    let mean = self.fixed_point_gate.qdiv(ctx, sum, cluster_size);
+                    gate.select(ctx, previous_centroids[cluster_id][i], mean, is_empty)
+                })
+                .collect();
+        }
+
+        centroids
+    }
+
+    /// Scales the given vector to unit norm, i.e. divides each element by the L2 norm.
+    fn normalize(
+        &self,
+        ctx: &mut Context<F>,
+        vector: &Vec<AssignedValue<F>>,
+    ) -> Vec<AssignedValue<F>> {
+        let norm_square = self.fixed_point_gate.inner_product(ctx, vector.clone(), vector.clone());
+        let norm = self.fixed_point_gate.qsqrt(ctx, norm_square);
+
+        vector.iter().map(|v| self.fixed_point_gate.qdiv(ctx, *v, norm)).collect()
+    }
+
+    /// Runs `I` iterations of K-means, starting with the first `K` vectors as centroids.
+    /// If `spherical` is set, centroids are normalized to unit norm after each update.
+    ///
+    /// Returns the centroids before the final update, the centroids after the final update,
+    /// and the cluster selectors of the final iteration.
+    fn kmeans_iterations<const K: usize, const I: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        vectors: &Vec<Vec<AssignedValue<F>>>,
+        distance: &dyn Fn(
+            &mut Context<F>,
+            &Vec<AssignedValue<F>>,
+            &Vec<AssignedValue<F>>,
+        ) -> AssignedValue<F>,
+        spherical: bool,
+    ) -> ([Vec<AssignedValue<F>>; K], [Vec<AssignedValue<F>>; K], Vec<[AssignedValue<F>; K]>) {
+        assert!(K < vectors.len());
+
+        // take first K vectors as the initial centroids
+        let mut centroids: [Vec<AssignedValue<F>>; K] = vectors
+            .iter()
+            .take(K)
+            .cloned()
+            .collect::<Vec<Vec<AssignedValue<F>>>>()
+            .try_into()
+            .unwrap();
+        let mut previous_centroids = centroids.clone();
+
+        let mut cluster_selectors: Vec<[AssignedValue<F>; K]> = vec![];
+
+        for _iter in 0..I {
+            cluster_selectors = self.assign_clusters::<K>(ctx, vectors, &centroids, distance);
+            previous_centroids = centroids;
+            centroids =
+                self.update_centroids::<K>(ctx, vectors, &cluster_selectors, &previous_centroids);
+            if spherical {
+                centroids = centroids.map(|c| self.normalize(ctx, &c));
+            }
+        }
+
+        (previous_centroids, centroids, cluster_selectors)
+    }
+
+    /// Runs `I` iterations of K-means over the vectors that are selected by the boolean `mask`,
+    /// starting with the first `K` selected vectors as centroids. If there are less than `K`
+    /// selected vectors they are repeated, i.e. the `k`-th centroid starts as the selected
+    /// vector of rank `k mod count`, so that no centroid is a zero vector (which has no cosine
+    /// distance). If no vector is selected, the first `K` vectors are taken instead; they
+    /// don't change as the clusters stay empty.
+    ///
+    /// Returns the centroids and the cluster selectors of the final iteration, where vectors
+    /// outside of the mask have all-zero selectors.
+    fn masked_kmeans<const K: usize, const I: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        vectors: &Vec<Vec<AssignedValue<F>>>,
+        mask: &Vec<AssignedValue<F>>,
+        distance: &dyn Fn(
+            &mut Context<F>,
+            &Vec<AssignedValue<F>>,
+            &Vec<AssignedValue<F>>,
+        ) -> AssignedValue<F>,
+    ) -> ([Vec<AssignedValue<F>>; K], Vec<[AssignedValue<F>; K]>) {
+        assert_eq!(vectors.len(), mask.len());
+        assert!(K <= vectors.len());
+        let gate = self.fixed_point_gate.gate();
+
+        // an empty mask picks the initial centroids among all vectors
+        let mask_size = gate.sum(ctx, mask.clone());
+        let is_empty = gate.is_zero(ctx, mask_size);
+        let init_mask: Vec<AssignedValue<F>> =
+            mask.iter().map(|m| gate.or(ctx, *m, is_empty)).collect();
+
+        // rank of each vector among the selected ones
+        let mut count = ctx.load_zero();
+        let ranks: Vec<AssignedValue<F>> = init_mask
+            .iter()
+            .map(|m| {
+                let rank = count;
+                count = gate.add(ctx, count, *m);
+                rank
+            })
+            .collect();
+
+        // take first K selected vectors as the initial centroids, repeating them if needed
+        let mut centroids: [Vec<AssignedValue<F>>; K] = core::array::from_fn(|k| {
+            // the rank is k, unless count <= k in which case it is k mod count
+            let mut target = ctx.load_constant(F::from(k as u64));
+            for c in 1..=k {
+                let is_count = gate.is_equal(ctx, count, Constant(F::from(c as u64)));
+                let offset = F::from((k % c) as u64) - F::from(k as u64);
+                target = gate.mul_add(ctx, is_count, Constant(offset), target);
+            }
+
+            let indicator: Vec<AssignedValue<F>> = init_mask
+                .iter()
+                .zip(ranks.iter())
+                .map(|(m, rank)| {
+                    let is_kth = gate.is_equal(ctx, *rank, target);
+                    gate.and(ctx, *m, is_kth)
+                })
+                .collect();
+
+            (0..vectors[0].len())
+                .map(|i| {
+                    gate.select_by_indicator(
+                        ctx,
+                        vectors.iter().map(|v| v[i]),
+                        indicator.iter().copied(),
+                    )
+                })
+                .collect()
+        });
+
+        let mut cluster_selectors: Vec<[AssignedValue<F>; K]> = vec![];
+
+        for _iter in 0..I {
+            // vectors outside of the mask do not belong to any cluster
+            cluster_selectors = self
+                .assign_clusters::<K>(ctx, vectors, &centroids, distance)
+                .into_iter()
+                .zip(mask.iter())
+                .map(|(selectors, m)| selectors.map(|s| gate.and(ctx, s, *m)))
+                .collect();
+            centroids = self.update_centroids::<K>(ctx, vectors, &cluster_selectors, &centroids);
+        }
+
+        (centroids, cluster_selectors)
+    }
+
+    /// Returns the cluster id of each vector, which is the index where its selector is set.
+    fn cluster_ids<const K: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        cluster_selectors: &Vec<[AssignedValue<F>; K]>,
+    ) -> Vec<AssignedValue<F>> {
+        cluster_selectors
+            .iter()
+            .map(|selectors| {
+                self.fixed_point_gate.gate().select_by_indicator(
+                    ctx,
+                    (0..K).map(|k| Constant(F::from(k as u64))),
+                    selectors.iter().copied(),
+                )
+            })
+            .collect()
+    }
+
+    /// Builds the result of K-means from the final centroids and cluster selectors,
+    /// deriving the quantized indicators and cluster ids along the way.
+    fn kmeans_result<const K: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        centroids: [Vec<AssignedValue<F>>; K],
+        cluster_selectors: Vec<[AssignedValue<F>; K]>,
+    ) -> KMeansResult<F, K> {
+        let gate = self.fixed_point_gate.gate();
+        let scale = Constant(self.fixed_point_gate.quantization_scale);
+
+        // a quantized one is the quantization scale itself, so we simply scale the selectors
+        let cluster_indicators: Vec<[AssignedValue<F>; K]> = cluster_selectors
+            .iter()
+            .map(|selectors| selectors.map(|s| gate.mul(ctx, s, scale)))
+            .collect();
+
+        let cluster_ids = self.cluster_ids::<K>(ctx, &cluster_selectors);
+
+        KMeansResult { centroids, cluster_indicators, cluster_selectors, cluster_ids }
+    }
+
+    /// Hashes `inputs` with the given domain `tag`, or without one if `tag` is `None`.
+    fn hash_with_tag<const T: usize, const RATE: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        poseidon: &mut PoseidonChip<F, T, RATE>,
+        tag: Option<u64>,
+        inputs: &[AssignedValue<F>],
+    ) -> AssignedValue<F> {
+        poseidon.clear();
+        if let Some(tag) = tag {
+            poseidon.update(&[ctx.load_constant(F::from(tag))]);
+        }
+        poseidon.update(inputs);
+        poseidon.squeeze(ctx, self.fixed_point_gate.gate()).unwrap()
+    }
+
+    /// Hashes a vector to a leaf with respect to the commitment `scheme`.
+    fn hash_leaf<const T: usize, const RATE: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        poseidon: &mut PoseidonChip<F, T, RATE>,
+        scheme: CommitmentScheme,
+        vector: &[AssignedValue<F>],
+    ) -> AssignedValue<F> {
+        match scheme {
+            CommitmentScheme::Plain => self.hash_with_tag(ctx, poseidon, None, vector),
+            CommitmentScheme::DomainSeparated => {
+                self.hash_with_tag(ctx, poseidon, Some(CommitmentScheme::LEAF_TAG), vector)
+            }
+        }
+    }
+
+    /// Hashes the children of an internal node with respect to the commitment `scheme`.
+    fn hash_node<const T: usize, const RATE: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        poseidon: &mut PoseidonChip<F, T, RATE>,
+        scheme: CommitmentScheme,
+        children: &[AssignedValue<F>],
+    ) -> AssignedValue<F> {
+        match scheme {
+            CommitmentScheme::Plain => self.hash_with_tag(ctx, poseidon, None, children),
+            CommitmentScheme::DomainSeparated => {
+                self.hash_with_tag(ctx, poseidon, Some(CommitmentScheme::NODE_TAG), children)
+            }
+        }
+    }
+
+    /// Returns the leaf that is used for padding with respect to the commitment `scheme`.
+    fn padding_leaf<const T: usize, const RATE: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        poseidon: &mut PoseidonChip<F, T, RATE>,
+        scheme: CommitmentScheme,
+    ) -> AssignedValue<F> {
+        match scheme {
+            CommitmentScheme::Plain => ctx.load_zero(),
+            CommitmentScheme::DomainSeparated => {
+                self.hash_with_tag(ctx, poseidon, Some(CommitmentScheme::PADDING_TAG), &[])
+            }
+        }
+    }
+
+    /// Hashes an entry of a sparse Merkle tree, i.e. its `id` along with the hashes of its
+    /// `vector` & `metadata`, to a leaf with respect to the commitment `scheme`.
+    fn hash_entry<const T: usize, const RATE: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        poseidon: &mut PoseidonChip<F, T, RATE>,
+        scheme: CommitmentScheme,
+        id: AssignedValue<F>,
+        vector: &[AssignedValue<F>],
+        metadata: &[AssignedValue<F>],
+    ) -> AssignedValue<F> {
+        let (metadata_tag, entry_tag) = match scheme {
+            CommitmentScheme::Plain => (None, None),
+            CommitmentScheme::DomainSeparated => {
+                (Some(CommitmentScheme::METADATA_TAG), Some(CommitmentScheme::ENTRY_TAG))
+            }
+        };
+
+        let vector_hash = self.hash_leaf(ctx, poseidon, scheme, vector);
+        let metadata_hash = self.hash_with_tag(ctx, poseidon, metadata_tag, metadata);
+        self.hash_with_tag(ctx, poseidon, entry_tag, &[id, vector_hash, metadata_hash])
+    }
+
+    /// Hashes a `vector` along with its `metadata` fields to a leaf with respect to the
+    /// commitment `scheme`, i.e. the hash of the vector hash & the metadata hash.
+    fn hash_record<const T: usize, const RATE: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        poseidon: &mut PoseidonChip<F, T, RATE>,
+        scheme: CommitmentScheme,
+        vector: &[AssignedValue<F>],
+        metadata: &[AssignedValue<F>],
+    ) -> AssignedValue<F> {
+        let (metadata_tag, record_tag) = match scheme {
+            CommitmentScheme::Plain => (None, None),
+            CommitmentScheme::DomainSeparated => {
+                (Some(CommitmentScheme::METADATA_TAG), Some(CommitmentScheme::RECORD_TAG))
+            }
+        };
+
+        let vector_hash = self.hash_leaf(ctx, poseidon, scheme, vector);
+        let metadata_hash = self.hash_with_tag(ctx, poseidon, metadata_tag, metadata);
+        self.hash_with_tag(ctx, poseidon, record_tag, &[vector_hash, metadata_hash])
+    }
+
+    /// Builds a Merkle tree over the given `leaves`, padding them to a power of `RATE`, and
+    /// returns its tree root (see `bind_root`).
+    fn merkle_tree_root<const T: usize, const RATE: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        poseidon: &mut PoseidonChip<F, T, RATE>,
+        scheme: CommitmentScheme,
+        leaves: Vec<AssignedValue<F>>,
+    ) -> AssignedValue<F> {
+        // extend leaves with padding to ensure number of leaves is a power of the arity,
+        // which is RATE so that each node is hashed with a single permutation
+        let num_hashes = leaves.len();
+        let mut num_leaves = 1;
+        while num_leaves < num_hashes {
+            num_leaves *= RATE;
+        }
+        let num_padding = num_leaves - num_hashes;
+
+        // construct merkle tree from the hashes & padding
+        let mut leaves: Vec<AssignedValue<F>> = leaves;
+        if num_padding > 0 {
+            let padding = self.padding_leaf(ctx, poseidon, scheme);
+            leaves.extend(vec![padding; num_padding])
+        }
+        assert_eq!(leaves.len(), num_leaves, "expected #leaves many leaves");
+
+        while leaves.len() > 1 {
+            // assert that the number of leaves is always a multiple of the arity
+            assert!(leaves.len() % RATE == 0);
+
+            leaves = leaves
+                .chunks(RATE)
+                .map(|children| self.hash_node(ctx, poseidon, scheme, children))
+                .collect();
+        }
+
+        // we must have reached the root node
+        assert!(leaves.len() == 1);
+
+        leaves[0]
+    }
+
+    /// Finds the `K` entries with the smallest `distances` in increasing order, where each
+    /// selected entry is masked to the sentinel for the next ones.
+    ///
+    /// The callers must constrain the distances to be below the sentinel (before any masking of
+    /// their own) with `assert_below_sentinel`, otherwise a selected entry could tie with an
+    /// unselected one and be returned twice, as ties are broken by the lowest index.
+    fn top_k_by_distance<const K: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        mut distances: Vec<AssignedValue<F>>,
+        vectors: &Vec<Vec<AssignedValue<F>>>,
+        metadata: &Vec<Vec<AssignedValue<F>>>,
+    ) -> [SearchResult<F>; K] {
+        assert!(K <= vectors.len(), "expected at least K vectors");
+        assert_eq!(vectors.len(), metadata.len(), "expected metadata for each vector");
+        let gate = self.fixed_point_gate.gate();
+
+        core::array::from_fn(|_| {
+            let (min, indicator) = self.argmin(ctx, &distances);
+
+            // mask out the selected entry for the next ones
+            distances = distances
+                .iter()
+                .zip(indicator.iter())
+                .map(|(d, s)| gate.select(ctx, Constant(self.sentinel()), *d, *s))
+                .collect();
+
+            self.search_result(ctx, min, indicator, vectors, metadata)
+        })
+    }
+
+    /// Returns whether the `attributes` of a vector satisfy all of the `conditions`.
+    fn satisfies(
+        &self,
+        ctx: &mut Context<F>,
+        attributes: &[AssignedValue<F>],
+        conditions: &[Condition<F>],
+    ) -> AssignedValue<F> {
+        let gate = self.fixed_point_gate.gate();
+
+        let mut all = ctx.load_constant(F::one());
+        for condition in conditions.iter() {
+            let a = attributes[condition.field];
+            let b = condition.value;
+            let holds = match condition.comparison {
+                Comparison::Equal => gate.is_equal(ctx, a, b),
+                Comparison::NotEqual => {
+                    let eq = gate.is_equal(ctx, a, b);
+                    gate.not(ctx, eq)
+                }
+                Comparison::LessThan => {
+                    let diff = gate.sub(ctx, a, b);
+                    self.fixed_point_gate.is_neg(ctx, diff)
+                }
+                Comparison::GreaterThan => {
+                    let diff = gate.sub(ctx, b, a);
+                    self.fixed_point_gate.is_neg(ctx, diff)
+                }
+                Comparison::LessOrEqual => {
+                    let diff = gate.sub(ctx, b, a);
+                    let gt = self.fixed_point_gate.is_neg(ctx, diff);
+                    gate.not(ctx, gt)
+                }
+                Comparison::GreaterOrEqual => {
+                    let diff = gate.sub(ctx, a, b);
+                    let lt = self.fixed_point_gate.is_neg(ctx, diff);
+                    gate.not(ctx, lt)
+                }
+            };
+            all = gate.and(ctx, all, holds);
+        }
+
+        all
+    }
+
+    /// Returns the search result with the given `distance`, selecting the vector & the
+    /// metadata of the entry with the same `indicator`.
+    fn search_result(
+        &self,
+        ctx: &mut Context<F>,
+        distance: AssignedValue<F>,
+        indicator: Vec<AssignedValue<F>>,
+        vectors: &Vec<Vec<AssignedValue<F>>>,
+        metadata: &Vec<Vec<AssignedValue<F>>>,
+    ) -> SearchResult<F> {
+        let gate = self.fixed_point_gate.gate();
+        let select = |ctx: &mut Context<F>, entries: &Vec<Vec<AssignedValue<F>>>| -> Vec<_> {
+            (0..entries[0].len())
+                .map(|i| {
+                    gate.select_by_indicator(
+                        ctx,
+                        entries.iter().map(|e| e[i]),
+                        indicator.iter().copied(),
+                    )
+                })
+                .collect()
+        };
+        let vector = select(ctx, vectors);
+        let metadata = select(ctx, metadata);
+
+        SearchResult { distance, indicator, vector, metadata }
+    }
+
+    /// Returns the leaf of a deleted vector, which is the same for all schemes.
+    fn tombstone_leaf<const T: usize, const RATE: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        poseidon: &mut PoseidonChip<F, T, RATE>,
+    ) -> AssignedValue<F> {
+        self.hash_with_tag(ctx, poseidon, Some(CommitmentScheme::TOMBSTONE_TAG), &[])
+    }
+
+    /// Hashes `inputs` followed by the `salt` with the domain `tag`.
+    ///
+    /// The tag is prepended regardless of the commitment scheme, as a query commitment and
+    /// a hashed result share the salt, and would otherwise reveal whether the two are equal.
+    fn hash_salted<const T: usize, const RATE: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        poseidon: &mut PoseidonChip<F, T, RATE>,
+        tag: u64,
+        inputs: &[AssignedValue<F>],
+        salt: AssignedValue<F>,
+    ) -> AssignedValue<F> {
+        let inputs: Vec<AssignedValue<F>> = inputs.iter().copied().chain([salt]).collect();
+        self.hash_with_tag(ctx, poseidon, Some(tag), &inputs)
+    }
+
+    /// Binds the number of vectors `len` and their dimension `dim` to the `tree_root`
+    /// with respect to the commitment `scheme`, returning the final root.
+    fn bind_root<const T: usize, const RATE: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        poseidon: &mut PoseidonChip<F, T, RATE>,
+        scheme: CommitmentScheme,
+        tree_root: AssignedValue<F>,
+        len: AssignedValue<F>,
+        dim: AssignedValue<F>,
+    ) -> AssignedValue<F> {
+        match scheme {
+            CommitmentScheme::Plain => tree_root,
+            CommitmentScheme::DomainSeparated => self.hash_with_tag(
+                ctx,
+                poseidon,
+                Some(CommitmentScheme::ROOT_TAG),
+                &[tree_root, len, dim],
+            ),
+        }
+    }
+
+    /// Encodes a quantized value as 32 big-endian bytes of its signed integer in two's
+    /// complement, i.e. the same way as an `int256` is encoded in the EVM.
+    ///
+    /// Valid values lie within `(-2^(2 * PRECISION_BITS + 1), 2^(2 * PRECISION_BITS + 1))`, so
+    /// shifting by that bound gives a small non-negative integer whose bits are unique. The low
+    /// bits of the shifted value are the low bits of the two's complement, and all higher bits
+    /// are the sign.
+    fn int256_bytes(&self, ctx: &mut Context<F>, a: AssignedValue<F>) -> Vec<AssignedValue<F>> {
+        let gate = self.fixed_point_gate.gate();
+        let num_bits = 2 * PRECISION_BITS as usize + 1;
+
+        let shifted = gate.add(ctx, a, Constant(F::from_u128(1u128 << num_bits)));
+        let bits = gate.num_to_bits(ctx, shifted, num_bits + 1);
+        let sign = gate.not(ctx, bits[num_bits]);
+
+        (0..32)
+            .rev()
+            .map(|byte| {
+                if 8 * byte >= num_bits {
+                    gate.mul(ctx, sign, Constant(F::from(255)))
+                } else {
+                    let byte_bits: Vec<AssignedValue<F>> = (8 * byte..8 * byte + 8)
+                        .map(|i| if i < num_bits { bits[i] } else { sign })
+                        .collect();
+                    gate.inner_product(ctx, byte_bits, (0..8).map(|i| Constant(F::from(1u64 << i))))
+                }
+            })
+            .collect()
+    }
+
+    /// Hashes a list of bytes with Keccak, returning the 32 bytes of the digest.
+    fn keccak_bytes(
+        &self,
+        ctx: &mut Context<F>,
+        keccak: &mut KeccakChip<F>,
+        bytes: Vec<AssignedValue<F>>,
+    ) -> Vec<AssignedValue<F>> {
+        let idx = keccak.keccak_fixed_len(ctx, self.fixed_point_gate.gate(), bytes, None);
+        keccak.fixed_len_queries[idx].output_assigned.clone()
+    }
+
+    /// Hashes a vector to a Keccak leaf, i.e. `keccak256(abi.encodePacked(vector))`
+    /// where `vector` is an `int256[]`.
+    fn keccak_leaf(
+        &self,
+        ctx: &mut Context<F>,
+        keccak: &mut KeccakChip<F>,
+        vector: &[AssignedValue<F>],
+    ) -> Vec<AssignedValue<F>> {
+        let bytes = vector.iter().flat_map(|a| self.int256_bytes(ctx, *a)).collect();
+        self.keccak_bytes(ctx, keccak, bytes)
+    }
+
+    /// Computes the Merkle tree root from a `leaf` at the given `index` along with its `siblings`,
+    /// where each node has `RATE` children (i.e. the arity is `RATE`, which must be a power of two).
+    ///
+    /// The siblings are given level by level starting from the leaf, with `RATE - 1` siblings at
+    /// each level in the order of their positions, and the digits of `index` in base `RATE` choose
+    /// the position of the current node at each level.
+    fn merkle_path_root<const T: usize, const RATE: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        poseidon: &mut PoseidonChip<F, T, RATE>,
+        scheme: CommitmentScheme,
+        leaf: AssignedValue<F>,
+        index: AssignedValue<F>,
+        siblings: &[AssignedValue<F>],
+    ) -> AssignedValue<F> {
+        assert!(RATE.is_power_of_two() && RATE > 1, "expected arity to be a power of two");
+        assert_eq!(siblings.len() % (RATE - 1), 0, "expected RATE - 1 siblings at each level");
+        let gate = self.fixed_point_gate.gate();
+        let depth = siblings.len() / (RATE - 1);
+        let digit_bits = RATE.trailing_zeros() as usize;
+
+        // the leaf is the root on its own
+        if depth == 0 {
+            gate.assert_is_const(ctx, &index, &F::zero());
+            return leaf;
+        }
+
+        // this also constrains the index to be less than RATE^depth
+        let bits = gate.num_to_bits(ctx, index, depth * digit_bits);
+
+        let mut node = leaf;
+        for (level_siblings, position_bits) in
+            siblings.chunks(RATE - 1).zip(bits.chunks(digit_bits))
+        {
+            // position of the node among its siblings
+            let position = gate.inner_product(
+                ctx,
+                position_bits.iter().copied(),
+                (0..digit_bits).map(|i| Constant(F::from(1u64 << i))),
+            );
+            let indicator = gate.idx_to_indicator(ctx, position, RATE);
+
+            // the children are the siblings with the node put at its position, so the j-th
+            // child is the node if it is at j, the j-th sibling if it is after j,
+            // and the (j - 1)-th sibling otherwise; `after` is whether the node is after j
+            let mut after = gate.sub(ctx, Constant(F::one()), indicator[0]);
+            let children: Vec<AssignedValue<F>> = (0..RATE)
+                .map(|j| {
+                    let sibling = if j == 0 {
+                        level_siblings[0]
+                    } else if j == RATE - 1 {
+                        level_siblings[RATE - 2]
+                    } else {
+                        after = gate.sub(ctx, after, indicator[j]);
+                        gate.select(ctx, level_siblings[j], level_siblings[j - 1], after)
+                    };
+                    gate.select(ctx, node, sibling, indicator[j])
+                })
+                .collect();
+
+            node = self.hash_node(ctx, poseidon, scheme, &children);
+        }
+
+        node
+    }
+}
+
+pub trait VectorDBInstructions<F: ScalarField, const PRECISION_BITS: u32> {
+    type FixedPointGate: FixedPointInstructions<F, PRECISION_BITS>;
+
+    fn fixed_point_gate(&self) -> &Self::FixedPointGate;
+
+    fn strategy(&self) -> VectorDBStrategy;
+
+    /// Given a `query` vector, returns the most similar vector
+    /// by doing an exhaustive search over all the given `vectors`
+    /// and with respect to provided `distance` function.
+    ///
+    /// Returns the closest (most similar) vector along with a minimum indicator
+    /// that is 1 at the index of the vector, and 0 on all other places.
+    fn nearest_vector(
+        &self,
+        ctx: &mut Context<F>,
+        query: &Vec<AssignedValue<F>>,
+        vectors: &Vec<Vec<AssignedValue<F>>>,
+        distance: &dyn Fn(
+            &mut Context<F>,
+            &Vec<AssignedValue<F>>,
+            &Vec<AssignedValue<F>>,
+        ) -> AssignedValue<F>,
+    ) -> (Vec<AssignedValue<F>>, Vec<AssignedValue<F>>)
+    where
+        F: ScalarField;
+
+    /// Finds the `K` entries closest to the `query` by doing an exhaustive search over all
+    /// the given `vectors` with respect to the provided `distance` function, in increasing
+    /// order of distance.
+    ///
+    /// Each result carries a one-hot indicator into the database, which selects both the
+    /// vector & the `metadata` of the entry, so that the metadata is proven to belong to
+    /// the closest entries. Ties are broken by the lowest index.
+    ///
+    /// Each distance is constrained to be below the sentinel that masks the selected entries
+    /// (i.e. `2^(PRECISION_BITS - 1)`), so that the results are distinct.
+    fn top_k<const K: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        query: &Vec<AssignedValue<F>>,
+        vectors: &Vec<Vec<AssignedValue<F>>>,
+        metadata: &Vec<Vec<AssignedValue<F>>>,
+        distance: &dyn Fn(
+            &mut Context<F>,
+            &Vec<AssignedValue<F>>,
+            &Vec<AssignedValue<F>>,
+        ) -> AssignedValue<F>,
+    ) -> [SearchResult<F>; K]
+    where
+        F: ScalarField;
+
+    /// Same as `nearest_vector`, but also returns the `metadata` of the closest entry,
+    /// selected with the same indicator as the vector. See `top_k`.
+    fn nearest_vector_with_metadata(
+        &self,
+        ctx: &mut Context<F>,
+        query: &Vec<AssignedValue<F>>,
+        vectors: &Vec<Vec<AssignedValue<F>>>,
+        metadata: &Vec<Vec<AssignedValue<F>>>,
+        distance: &dyn Fn(
+            &mut Context<F>,
+            &Vec<AssignedValue<F>>,
+            &Vec<AssignedValue<F>>,
+        ) -> AssignedValue<F>,
+    ) -> SearchResult<F>
+    where
+        F: ScalarField;
+
+    /// Same as `top_k`, but over a database with a fixed capacity, i.e. `vectors` has a slot for
+    /// each of `capacity` many vectors and only the first `len` of them are active. The distances
+    /// of the padding slots are masked to a sentinel, and each result is constrained to be an
+    /// active one, so `len` must be at least `K`.
+    ///
+    /// The circuit only depends on the capacity, so the same keys can be used as the database grows.
+    fn top_k_with_length<const K: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        query: &Vec<AssignedValue<F>>,
+        vectors: &Vec<Vec<AssignedValue<F>>>,
+        metadata: &Vec<Vec<AssignedValue<F>>>,
+        len: AssignedValue<F>,
+        distance: &dyn Fn(
+            &mut Context<F>,
+            &Vec<AssignedValue<F>>,
+            &Vec<AssignedValue<F>>,
+        ) -> AssignedValue<F>,
+    ) -> [SearchResult<F>; K]
+    where
+        F: ScalarField;
+
+    /// Same as `nearest_vector`, but only among the first `len` of the `vectors`, see `top_k_with_length`.
+    fn nearest_vector_with_length(
+        &self,
+        ctx: &mut Context<F>,
+        query: &Vec<AssignedValue<F>>,
+        vectors: &Vec<Vec<AssignedValue<F>>>,
+        len: AssignedValue<F>,
+        distance: &dyn Fn(
+            &mut Context<F>,
+            &Vec<AssignedValue<F>>,
+            &Vec<AssignedValue<F>>,
+        ) -> AssignedValue<F>,
+    ) -> (Vec<AssignedValue<F>>, Vec<AssignedValue<F>>)
+    where
+        F: ScalarField;
+
+    /// Same as `nearest_vector_with_metadata`, but only among the vectors whose `attributes`
+    /// satisfy all of the `conditions`, e.g. `tenant = X` and `date >= Y`. The attributes are
+    /// returned as the metadata of the result, and can be committed along with the vectors
+    /// via `merkle_commitment_with_metadata` so that the verifier knows they are the right ones.
+    ///
+    /// The distances of the non-matching vectors are masked to a sentinel, i.e. `2^(PRECISION_BITS - 1)`,
+    /// and every distance is constrained to be below it, so the minimum proves that no matching
+    /// vector is closer than the result. Also returns a boolean that is 1 if the result matches,
+    /// i.e. if any vector does.
+    fn filtered_nearest_vector(
+        &self,
+        ctx: &mut Context<F>,
+        query: &Vec<AssignedValue<F>>,
+        vectors: &Vec<Vec<AssignedValue<F>>>,
+        attributes: &Vec<Vec<AssignedValue<F>>>,
+        conditions: &[Condition<F>],
+        distance: &dyn Fn(
+            &mut Context<F>,
+            &Vec<AssignedValue<F>>,
+            &Vec<AssignedValue<F>>,
+        ) -> AssignedValue<F>,
+    ) -> (SearchResult<F>, AssignedValue<F>)
+    where
+        F: ScalarField;
+
+    /// Finds all vectors within distance `threshold` (inclusive) of the `query` with respect
+    /// to the provided `distance` function, which can be any metric of the `DistanceChip`.
+    /// The threshold is usually a public input.
+    ///
+    /// Returns a boolean membership for each vector along with the number of matches, and
+    /// commits to the matches with a Merkle tree over the database where the leaf of each
+    /// non-matching vector is replaced with padding. The commitment binds the number of
+    /// matches instead of the number of vectors, with respect to the commitment `scheme`.
+    fn range_query<const T: usize, const RATE: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        poseidon: &mut PoseidonChip<F, T, RATE>,
+        scheme: CommitmentScheme,
+        query: &Vec<AssignedValue<F>>,
+        vectors: &Vec<Vec<AssignedValue<F>>>,
+        threshold: AssignedValue<F>,
+        distance: &dyn Fn(
+            &mut Context<F>,
+            &Vec<AssignedValue<F>>,
+            &Vec<AssignedValue<F>>,
+        ) -> AssignedValue<F>,
+    ) -> RangeQueryResult<F>
+    where
+        F: ScalarField;
+
+    /// Proves that the `query` matches some vector of the database within distance `threshold`,
+    /// without revealing which one, for biometric-style membership statements.
+    ///
+    /// Returns the Merkle root over the database with respect to the commitment `scheme` & the
+    /// commitment to the query with the blinding `salt` (see `commit_query`), along with a boolean
+    /// that is 1 if the closest vector is within the threshold. These are all that should be made
+    /// public, so the query and the matched vector stay hidden. The commitment is always tagged,
+    /// so that it can't be compared against a leaf of a candidate vector, and the salt must be
+    /// random so that a query with little entropy (e.g. a biometric template) can't be recovered.
+    fn threshold_match<const T: usize, const RATE: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        poseidon: &mut PoseidonChip<F, T, RATE>,
+        scheme: CommitmentScheme,
+        query: &Vec<AssignedValue<F>>,
+        salt: AssignedValue<F>,
+        vectors: &Vec<Vec<AssignedValue<F>>>,
+        threshold: AssignedValue<F>,
+        distance: &dyn Fn(
+            &mut Context<F>,
+            &Vec<AssignedValue<F>>,
+            &Vec<AssignedValue<F>>,
+        ) -> AssignedValue<F>,
+    ) -> ThresholdMatch<F>
+    where
+        F: ScalarField;
+
+    /// Commits to the `query` with a blinding `salt`, i.e. `Poseidon(QUERY_TAG, query, salt)`.
+    /// Unlike the plain query hash, this hides the query from the verifier as long as the salt
+    /// is random, while the client can later open the commitment to prove that the answer
+    /// corresponds to their own query. The tag is always prepended, see `public_result`.
+    fn commit_query<const T: usize, const RATE: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        poseidon: &mut PoseidonChip<F, T, RATE>,
+        query: &Vec<AssignedValue<F>>,
+        salt: AssignedValue<F>,
+    ) -> AssignedValue<F>
+    where
+        F: ScalarField;
+
+    /// Returns the values to make public for a `result`, depending on its `visibility`: either
+    /// the result itself, or only its hash `Poseidon(RESULT_TAG, result, salt)` where the `salt`
+    /// should be the same as the one of the query commitment. The distinct tags keep the hashed
+    /// result from revealing whether it is equal to the query.
+    fn public_result<const T: usize, const RATE: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        poseidon: &mut PoseidonChip<F, T, RATE>,
+        visibility: ResultVisibility,
+        result: &Vec<AssignedValue<F>>,
+        salt: AssignedValue<F>,
+    ) -> Vec<AssignedValue<F>>
+    where
+        F: ScalarField;
+
+    /// Finds the closest vector for each of the `queries` within a single circuit, so that the
+    /// database is loaded and committed (with respect to the commitment `scheme`) only once.
+    ///
+    /// Per-vector work that does not depend on the query, such as norms, is computed once by
+    /// `precompute` for each vector and each query, and is given to `distance` along with them
+    /// as `distance(ctx, vector, vector_pre, query, query_pre)`. For example, cosine distance
+    /// shares norms via `DistanceInstructions::norm` & `cosine_distance_with_norms`, while
+    /// metrics without precomputation can return zero.
+    fn batch_nearest_vector<const T: usize, const RATE: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        poseidon: &mut PoseidonChip<F, T, RATE>,
+        scheme: CommitmentScheme,
+        queries: &Vec<Vec<AssignedValue<F>>>,
+        vectors: &Vec<Vec<AssignedValue<F>>>,
+        precompute: &dyn Fn(&mut Context<F>, &Vec<AssignedValue<F>>) -> AssignedValue<F>,
+        distance: &dyn Fn(
+            &mut Context<F>,
+            &Vec<AssignedValue<F>>,
+            AssignedValue<F>,
+            &Vec<AssignedValue<F>>,
+            AssignedValue<F>,
+        ) -> AssignedValue<F>,
+    ) -> BatchSearchResult<F>
+    where
+        F: ScalarField;
+
+    /// Commits to a two-level (IVF) index, i.e. a Merkle tree over the `centroids` where each
+    /// leaf also binds the root of its cluster, as in `merkle_commitment_with_metadata` with
+    /// the cluster root as the only metadata field. The cluster roots are usually the ones
+    /// returned by `index_commitment` with the same commitment `scheme`, as `ivf_query`
+    /// recomputes them with it.
+    fn ivf_commitment<const T: usize, const RATE: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        poseidon: &mut PoseidonChip<F, T, RATE>,
+        scheme: CommitmentScheme,
+        centroids: &Vec<Vec<AssignedValue<F>>>,
+        cluster_roots: &Vec<AssignedValue<F>>,
+    ) -> AssignedValue<F>
+    where
+        F: ScalarField;
+
+    /// Answers a query against a two-level (IVF) index within a single circuit:
+    ///
+    /// - commits to the `centroids` along with their `cluster_roots` via `ivf_commitment`
+    /// - finds the nearest centroid, selecting its cluster root with the same indicator
+    /// - commits to the given `cluster` and constrains its root to be the selected one
+    /// - finds the nearest vector within the cluster
+    ///
+    /// So the result is proven to be the nearest vector of the cluster of the nearest centroid,
+    /// and only the IVF root & the result need to be public. Note that the circuit depends on
+    /// the size of the selected cluster.
+    fn ivf_query<const T: usize, const RATE: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        poseidon: &mut PoseidonChip<F, T, RATE>,
+        scheme: CommitmentScheme,
+        query: &Vec<AssignedValue<F>>,
+        centroids: &Vec<Vec<AssignedValue<F>>>,
+        cluster_roots: &Vec<AssignedValue<F>>,
+        cluster: &Vec<Vec<AssignedValue<F>>>,
+        distance: &dyn Fn(
+            &mut Context<F>,
+            &Vec<AssignedValue<F>>,
+            &Vec<AssignedValue<F>>,
+        ) -> AssignedValue<F>,
+    ) -> IvfQueryResult<F>
+    where
+        F: ScalarField;
+
+    /// Same as `ivf_query`, but probes the `P` nearest centroids instead of only the nearest one,
+    /// which improves the recall for queries near the cluster boundaries:
+    ///
+    /// - the probed centroids are found with `top_k`, so they are constrained to be the `P` closest,
+    ///   and to be distinct as `top_k` bounds each centroid distance below the sentinel
+    /// - each of the given `clusters` must hash to the root of the respective probed centroid,
+    ///   where an empty cluster must have a zero root as in `index_commitment`
+    /// - the nearest vector is found within the union of the clusters
+    fn ivf_query_multi_probe<const P: usize, const T: usize, const RATE: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        poseidon: &mut PoseidonChip<F, T, RATE>,
+        scheme: CommitmentScheme,
+        query: &Vec<AssignedValue<F>>,
+        centroids: &Vec<Vec<AssignedValue<F>>>,
+        cluster_roots: &Vec<AssignedValue<F>>,
+        clusters: &[Vec<Vec<AssignedValue<F>>>; P],
+        distance: &dyn Fn(
+            &mut Context<F>,
+            &Vec<AssignedValue<F>>,
+            &Vec<AssignedValue<F>>,
+        ) -> AssignedValue<F>,
+    ) -> IvfMultiProbeResult<F, P>
+    where
+        F: ScalarField;
+
+    /// Commits to an array of vectors using Merkle tree with Poseidon hash.
+    ///
+    /// Each node has `RATE` children, so that it is hashed with a single permutation. If
+    /// the given `vectors` does not have a power of `RATE` many elements, it will add zeros
+    /// to the leaves to make up for the remaining leaves.
+    fn merkle_commitment<const T: usize, const RATE: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        poseidon: &mut PoseidonChip<F, T, RATE>,
+        vectors: &Vec<Vec<AssignedValue<F>>>,
+    ) -> AssignedValue<F>
+    where
+        F: ScalarField;
+
+    /// Commits to an array of vectors using Merkle tree with Poseidon hash, with respect
+    /// to the given commitment `scheme`.
+    ///
+    /// With [`CommitmentScheme::Plain`] this is the same as `merkle_commitment`. With
+    /// [`CommitmentScheme::DomainSeparated`], leaves, padding leaves and internal nodes are
+    /// domain-separated so that they can't be confused with each other, and the root binds
+    /// the number of vectors & their dimension so that padded and unpadded databases can't collide.
+    fn merkle_commitment_with_scheme<const T: usize, const RATE: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        poseidon: &mut PoseidonChip<F, T, RATE>,
+        scheme: CommitmentScheme,
+        vectors: &Vec<Vec<AssignedValue<F>>>,
+    ) -> AssignedValue<F>
+    where
+        F: ScalarField;
+
+    /// Same as `merkle_commitment_with_scheme`, but each leaf also binds the `metadata`
+    /// fields of its vector (e.g. a document id or a label), so that a search result can be
+    /// returned along with its payload. The leaf is the hash of the vector hash & the
+    /// metadata hash, and all vectors should have the same number of metadata fields.
+    fn merkle_commitment_with_metadata<const T: usize, const RATE: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        poseidon: &mut PoseidonChip<F, T, RATE>,
+        scheme: CommitmentScheme,
+        vectors: &Vec<Vec<AssignedValue<F>>>,
+        metadata: &Vec<Vec<AssignedValue<F>>>,
+    ) -> AssignedValue<F>
+    where
+        F: ScalarField;
+
+    /// Same as `merkle_commitment_with_scheme`, but over a database with a fixed capacity where
+    /// only the first `len` of the `vectors` are active. The leaves of the padding slots are
+    /// replaced by padding leaves, and `len` is bound to the root instead of the capacity, so the
+    /// root is the same as a tree over the active vectors with room for `vectors.len()` many.
+    fn merkle_commitment_with_length<const T: usize, const RATE: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        poseidon: &mut PoseidonChip<F, T, RATE>,
+        scheme: CommitmentScheme,
+        vectors: &Vec<Vec<AssignedValue<F>>>,
+        len: AssignedValue<F>,
+    ) -> AssignedValue<F>
+    where
+        F: ScalarField;
+
+    /// Same as `merkle_commitment_with_scheme`, but with the Poseidon instance & the scheme
+    /// given by `params`, so that it can't drift from the native trees.
+    fn merkle_commitment_with_params<const T: usize, const RATE: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        params: &CommitmentParams<T, RATE>,
+        vectors: &Vec<Vec<AssignedValue<F>>>,
+    ) -> AssignedValue<F>
+    where
+        F: ScalarField;
+
+    /// Commits to a set of vectors using a binary Merkle tree with Keccak hashes, so that the
+    /// root can be recomputed cheaply by smart contracts. Returns the 32 bytes of the root,
+    /// most significant first.
+    ///
+    /// Each leaf is `keccak256(abi.encodePacked(vector))`, where every element is encoded as
+    /// an `int256` holding its quantized value, and each node is `keccak256(left ++ right)`.
+    /// If the number of vectors is not a power of two, the remaining leaves are `bytes32(0)`.
+    ///
+    /// The Keccak computations themselves are only constrained in the second phase of the
+    /// `EthCircuitBuilder`, so this must be used within a circuit built with `run_eth`.
+    fn keccak_merkle_commitment(
+        &self,
+        ctx: &mut Context<F>,
+        keccak: &mut KeccakChip<F>,
+        vectors: &Vec<Vec<AssignedValue<F>>>,
+    ) -> Vec<AssignedValue<F>>
+    where
+        F: ScalarField;
+
+    /// Verifies that `vector` is at the given `index` within the Merkle tree with the given
+    /// `root`, using its authentication path `siblings`, which has the `RATE - 1` siblings at each
+    /// level starting from the siblings of the leaf.
+    ///
+    /// The vector is hashed the same way as the leaves of `merkle_commitment`, so it takes
+    /// O(log N) hashes instead of re-hashing the entire database.
+    fn verify_merkle_path<const T: usize, const RATE: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        poseidon: &mut PoseidonChip<F, T, RATE>,
+        root: AssignedValue<F>,
+        vector: &Vec<AssignedValue<F>>,
+        index: AssignedValue<F>,
+        siblings: &Vec<AssignedValue<F>>,
+    ) where
+        F: ScalarField;
+
+    /// Same as `verify_merkle_path`, but with respect to the given commitment `scheme`, where
+    /// `len` is the number of vectors that the root binds (ignored for [`CommitmentScheme::Plain`]).
+    fn verify_merkle_path_with_scheme<const T: usize, const RATE: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        poseidon: &mut PoseidonChip<F, T, RATE>,
+        scheme: CommitmentScheme,
+        root: AssignedValue<F>,
+        len: AssignedValue<F>,
+        vector: &Vec<AssignedValue<F>>,
+        index: AssignedValue<F>,
+        siblings: &Vec<AssignedValue<F>>,
+    ) where
+        F: ScalarField;
+
+    /// Proves an `update` to the database committed with `old_root` via `merkle_commitment_with_scheme`,
+    /// where `len` is the number of vectors (including deleted ones) and `siblings` is the
+    /// authentication path of the slot at `index`, which is the same before & after the update.
+    ///
+    /// - [`MerkleUpdate::Insert`] puts `new_vector` into the empty slot at index `len`, `old_vector` is ignored;
+    ///   this requires [`CommitmentScheme::DomainSeparated`], as only then the root binds `len`
+    /// - [`MerkleUpdate::Replace`] replaces `old_vector` at `index` with `new_vector`
+    /// - [`MerkleUpdate::Delete`] replaces `old_vector` at `index` with a tombstone, `new_vector` is ignored
+    ///
+    /// Returns the new root along with the new number of vectors, so that updates can be chained.
+    fn merkle_update<const T: usize, const RATE: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        poseidon: &mut PoseidonChip<F, T, RATE>,
+        scheme: CommitmentScheme,
+        update: MerkleUpdate,
+        old_root: AssignedValue<F>,
+        len: AssignedValue<F>,
+        index: AssignedValue<F>,
+        siblings: &Vec<AssignedValue<F>>,
+        old_vector: &Vec<AssignedValue<F>>,
+        new_vector: &Vec<AssignedValue<F>>,
+    ) -> (AssignedValue<F>, AssignedValue<F>)
+    where
+        F: ScalarField;
+
+    /// Proves that appending `vectors` to an append-only (i.e. incremental) Merkle tree moves
+    /// `old_root` to a new root, where `len` is the number of vectors already in the tree and
+    /// `frontier` is the rightmost left node at each level (see `IncrementalMerkleTree` under
+    /// the `native` module). The depth of the tree is the length of the frontier.
+    ///
+    /// The leaves are hashed the same way as `merkle_commitment_with_scheme`, and the root is
+    /// exactly that of `merkle_commitment_with_length` over `2^depth` slots holding the vectors,
+    /// with the same `scheme` and `len` set to the number of vectors (i.e. a native `MerkleTree`
+    /// with a capacity of `2^depth`). Under [`CommitmentScheme::DomainSeparated`] both bind the
+    /// number of vectors & their dimension, not the capacity. So it is equal to the root of
+    /// `merkle_commitment_with_scheme` over the same vectors only when their number padded to
+    /// a power of two is `2^depth`. The tree is binary, so `RATE` must be 2.
+    ///
+    /// Returns the new root, the new number of vectors and the new frontier, so that batches
+    /// can be chained.
+    fn merkle_append<const T: usize, const RATE: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        poseidon: &mut PoseidonChip<F, T, RATE>,
+        scheme: CommitmentScheme,
+        old_root: AssignedValue<F>,
+        len: AssignedValue<F>,
+        frontier: &Vec<AssignedValue<F>>,
+        vectors: &Vec<Vec<AssignedValue<F>>>,
+    ) -> (AssignedValue<F>, AssignedValue<F>, Vec<AssignedValue<F>>)
+    where
+        F: ScalarField;
+
+    /// Verifies that the sparse Merkle tree with the given `root` has an entry with the given
+    /// `id`, holding `vector` along with its `metadata`, using the authentication path `siblings`
+    /// of the leaf at index `id` (see `SparseMerkleTree` under the `native` module).
+    ///
+    /// The depth of the tree is given by the number of siblings (`RATE - 1` at each level), and
+    /// `id` is constrained to be within it.
+    fn verify_sparse_inclusion<const T: usize, const RATE: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        poseidon: &mut PoseidonChip<F, T, RATE>,
+        scheme: CommitmentScheme,
+        root: AssignedValue<F>,
+        id: AssignedValue<F>,
+        vector: &Vec<AssignedValue<F>>,
+        metadata: &Vec<AssignedValue<F>>,
+        siblings: &Vec<AssignedValue<F>>,
+    ) where
+        F: ScalarField;
+
+    /// Verifies that the sparse Merkle tree with the given `root` has no entry with the given
+    /// `id`, i.e. the leaf at index `id` is empty, using its authentication path `siblings`.
+    fn verify_sparse_non_inclusion<const T: usize, const RATE: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        poseidon: &mut PoseidonChip<F, T, RATE>,
+        scheme: CommitmentScheme,
+        root: AssignedValue<F>,
+        id: AssignedValue<F>,
+        siblings: &Vec<AssignedValue<F>>,
+    ) where
+        F: ScalarField;
+
+    /// K-means algorithm to compute `K` centroids from a given set of vectors.
+    /// Since the algorithm can't stop execution based on convergence, we instead
+    /// opt for a fixed-iteration approach.
+    ///
+    /// - K: number of centroids
+    /// - I: number of iterations
+    ///
+    /// Returns the centroids, along with the cluster of each vector given as quantized
+    /// indicators, boolean indicators (both one-hot encoded) and cluster ids.
+    fn kmeans<const K: usize, const I: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        vectors: &Vec<Vec<AssignedValue<F>>>,
+        distance: &dyn Fn(
+            &mut Context<F>,
+            &Vec<AssignedValue<F>>,
+            &Vec<AssignedValue<F>>,
+        ) -> AssignedValue<F>,
+    ) -> KMeansResult<F, K>
+    where
+        F: ScalarField;
+
+    /// Same as `kmeans`, but only over the first `len` of the `vectors`, which are the active
+    /// slots of a database with a fixed capacity. The padding slots do not belong to any cluster,
+    /// i.e. their selectors & indicators are all zero, and they have a cluster id of zero.
+    fn kmeans_with_length<const K: usize, const I: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        vectors: &Vec<Vec<AssignedValue<F>>>,
+        len: AssignedValue<F>,
+        distance: &dyn Fn(
+            &mut Context<F>,
+            &Vec<AssignedValue<F>>,
+            &Vec<AssignedValue<F>>,
+        ) -> AssignedValue<F>,
+    ) -> KMeansResult<F, K>
+    where
+        F: ScalarField;
+
+    /// Spherical K-means algorithm, which is the same as `kmeans` except that centroids
+    /// are normalized to unit norm after each update. This is meant to be used with
+    /// normalized vectors and `cosine_distance`, where the mean itself is not unit-norm.
+    ///
+    /// - K: number of centroids
+    /// - I: number of iterations
+    ///
+    /// Returns the normalized centroids, along with the cluster of each vector as in `kmeans`.
+    fn spherical_kmeans<const K: usize, const I: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        vectors: &Vec<Vec<AssignedValue<F>>>,
+        distance: &dyn Fn(
+            &mut Context<F>,
+            &Vec<AssignedValue<F>>,
+            &Vec<AssignedValue<F>>,
+        ) -> AssignedValue<F>,
+    ) -> KMeansResult<F, K>
+    where
+        F: ScalarField;
+
+    /// Hierarchical K-means algorithm that builds a tree of centroids with a fixed `depth`
+    /// and branching factor `B`, which is bisecting K-means for `B = 2`. The vectors are
+    /// clustered into `B` clusters, and then each cluster is clustered into `B` clusters
+    /// again, until the tree reaches the given depth.
+    ///
+    /// Each clustering is done with `kmeans` over the members of the parent cluster only,
+    /// taking its first `B` members as the initial centroids. A cluster with less than `B`
+    /// members repeats them, so that the initial centroids are never zero vectors and the
+    /// cosine distance can be used as well; an empty cluster takes the first `B` vectors.
+    ///
+    /// Note that a distance is computed between every vector and every centroid at each
+    /// level, so this costs at least as many constraints as a flat `kmeans` with `B^depth`
+    /// centroids; it is meant for building multi-level indexes, not for saving constraints.
+    ///
+    /// - B: branching factor, number of centroids for each cluster
+    /// - I: number of iterations for each clustering
+    ///
+    /// Returns the centroids at each level along with a Merkle commitment to each level,
+    /// with respect to the commitment `scheme`.
+    /// When `B` is a power of `RATE`, children of a centroid make up a subtree in the commitment
+    /// of their level, so that a multi-level search can open them together. Also returns the
+    /// leaf cluster of each vector, as boolean indicators and cluster ids.
+    fn hierarchical_kmeans<const B: usize, const I: usize, const T: usize, const RATE: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        poseidon: &mut PoseidonChip<F, T, RATE>,
+        scheme: CommitmentScheme,
+        vectors: &Vec<Vec<AssignedValue<F>>>,
+        depth: usize,
+        distance: &dyn Fn(
+            &mut Context<F>,
+            &Vec<AssignedValue<F>>,
+            &Vec<AssignedValue<F>>,
+        ) -> AssignedValue<F>,
+    ) -> HierarchicalKMeansResult<F>
+    where
+        F: ScalarField;
+
+    /// Same as `kmeans`, but also reports how well the centroids have converged,
+    /// since the algorithm runs for a fixed number of iterations `I`.
+    ///
+    /// Returns the K-means result along with the K-means objective w.r.t. the final
+    /// centroids, and the movement of centroids in the final iteration.
+    fn kmeans_with_convergence<const K: usize, const I: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        vectors: &Vec<Vec<AssignedValue<F>>>,
+        distance: &dyn Fn(
+            &mut Context<F>,
+            &Vec<AssignedValue<F>>,
+            &Vec<AssignedValue<F>>,
+        ) -> AssignedValue<F>,
+    ) -> (KMeansResult<F, K>, KMeansConvergence<F>)
+    where
+        F: ScalarField;
+
+    /// Computes the K-means objective (i.e. inertia), that is the sum of squared
+    /// distances of each vector to the centroid it is assigned to.
+    ///
+    /// The assignments are given by boolean `cluster_selectors`, such as the ones
+    /// returned by `kmeans`.
+    ///
+    /// This is the inertia only for the Euclidean distance; for any other `distance` it is
+    /// the sum of squares of that distance (e.g. squared L1 norms for the Manhattan distance),
+    /// which K-means does not minimize. The sum is constrained to lie within the fixed-point
+    /// range, i.e. below `2^(PRECISION_BITS + 1)`, so that it can't silently overflow.
+    fn kmeans_objective<const K: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        vectors: &Vec<Vec<AssignedValue<F>>>,
+        centroids: &[Vec<AssignedValue<F>>; K],
+        cluster_selectors: &Vec<[AssignedValue<F>; K]>,
+        distance: &dyn Fn(
+            &mut Context<F>,
+            &Vec<AssignedValue<F>>,
+            &Vec<AssignedValue<F>>,
+        ) -> AssignedValue<F>,
+    ) -> AssignedValue<F>
+    where
+        F: ScalarField;
+
+    /// K-medoids algorithm to find `K` medoids among a given set of vectors, such that
+    /// each medoid is an actual member of the set. Unlike `kmeans`, this does not take the
+    /// mean of vectors, and therefore works with any distance function, including the
+    /// non-Euclidean ones.
+    ///
+    /// The metric must however be symmetric, and zero for a vector and itself: only the
+    /// distances `distance(vectors[i], vectors[j])` with `i < j` are computed, and the
+    /// rest of the pairwise distances are derived from these.
+    ///
+    /// Each iteration assigns the vectors to their closest medoid, and then picks the
+    /// member with the least total distance to other members as the new medoid of each
+    /// cluster. The first `K` vectors are taken as the initial medoids, and an empty
+    /// cluster keeps its previous medoid (same as `kmeans` keeps its previous centroid).
+    /// The total distance of each member is constrained to be below the sentinel that
+    /// masks the non-members.
+    ///
+    /// - K: number of medoids
+    /// - I: number of iterations
+    ///
+    /// Returns the medoids along with their indices and indicators (one-hot encoded) into
+    /// the input vectors, and the cluster of each vector as in `kmeans`.
+    fn kmedoids<const K: usize, const I: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        vectors: &Vec<Vec<AssignedValue<F>>>,
+        distance: &dyn Fn(
+            &mut Context<F>,
+            &Vec<AssignedValue<F>>,
+            &Vec<AssignedValue<F>>,
+        ) -> AssignedValue<F>,
+    ) -> KMedoidsResult<F, K>
+    where
+        F: ScalarField;
+
+    /// Given a set of vectors and a mean vector, asserts that the mean of these vectors is
+    /// equal to the given vector; and returns a Merkle commitment to the given vectors with
+    /// respect to the commitment `scheme`.
+    ///
+    /// This is mostly used for cluster commitments at the end of K-means, where the mean
+    /// is computed the same way as K-means does.
+    ///
+    /// Note that an adversary could construct another set of vectors with the same mean,
+    /// so the vectors should be bound to the database with `assert_cluster_membership`.
+    fn mean_merkle<const T: usize, const RATE: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        poseidon: &mut PoseidonChip<F, T, RATE>,
+        scheme: CommitmentScheme,
+        vectors: &Vec<Vec<AssignedValue<F>>>,
+        expected_mean: &Vec<AssignedValue<F>>,
+    ) -> AssignedValue<F>
+    where
+        F: ScalarField;
+
+    /// Asserts that `cluster` consists of exactly the vectors among `vectors` that are
+    /// selected by the boolean `selectors`, in the same order. The selectors are usually a
+    /// column of the cluster selectors returned by `kmeans`, and they are constrained to be
+    /// bits here as well.
+    ///
+    /// This binds the cluster to the database, so that no other set of vectors (e.g. one
+    /// that has the same mean) can be committed as the cluster.
+    fn assert_cluster_membership(
+        &self,
+        ctx: &mut Context<F>,
+        vectors: &Vec<Vec<AssignedValue<F>>>,
+        selectors: &Vec<AssignedValue<F>>,
+        cluster: &Vec<Vec<AssignedValue<F>>>,
+    ) where
+        F: ScalarField;
+
+    /// Commits to an index built with `kmeans`, proving that the given `clusters` are
+    /// a partition of the database `vectors`:
+    ///
+    /// - every vector belongs to exactly one cluster, i.e. the cluster selectors are boolean
+    ///   and each vector has exactly one of them set
+    /// - each cluster consists of exactly the vectors selected for it, in database order
+    ///
+    /// Returns the Merkle roots over the database, the centroids and each cluster with respect
+    /// to the commitment `scheme`, all computed within the same circuit. An empty cluster has
+    /// a zero root.
+    fn index_commitment<const K: usize, const T: usize, const RATE: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        poseidon: &mut PoseidonChip<F, T, RATE>,
+        scheme: CommitmentScheme,
+        vectors: &Vec<Vec<AssignedValue<F>>>,
+        result: &KMeansResult<F, K>,
+        clusters: &[Vec<Vec<AssignedValue<F>>>; K],
+    ) -> IndexCommitment<F>
+    where
+        F: ScalarField;
+}
+
+impl<'a, F: ScalarField, const PRECISION_BITS: u32> VectorDBInstructions<F, PRECISION_BITS>
+    for VectorDBChip<'a, F, PRECISION_BITS>
+{
+    type FixedPointGate = FixedPointChip<F, PRECISION_BITS>;
+
+    fn fixed_point_gate(&self) -> &Self::FixedPointGate {
+        &self.fixed_point_gate
+    }
+
+    fn strategy(&self) -> VectorDBStrategy {
+        self.strategy
+    }
+
+    fn nearest_vector(
+        &self,
+        ctx: &mut Context<F>,
+        query: &Vec<AssignedValue<F>>,
+        vectors: &Vec<Vec<AssignedValue<F>>>,
+        distance: &dyn Fn(
+            &mut Context<F>,
+            &Vec<AssignedValue<F>>,
+            &Vec<AssignedValue<F>>,
+        ) -> AssignedValue<F>,
+    ) -> (Vec<AssignedValue<F>>, Vec<AssignedValue<F>>)
+    where
+        F: ScalarField,
+    {
+        // compute distance to each vector
+        let distances: Vec<AssignedValue<F>> =
+            vectors.iter().map(|v| distance(ctx, v, query)).collect();
+
+        // find the minimum
+        let min: AssignedValue<F> = distances
+            .clone()
+            .into_iter()
+            .reduce(|acc, d| self.fixed_point_gate.qmin(ctx, acc, d))
+            .unwrap();
+        let min_indicator: Vec<AssignedValue<F>> = distances
+            .into_iter()
+            .map(|d| self.fixed_point_gate.gate().is_equal(ctx, min, d))
+            .collect();
+
+        // get the most similar vector by selecting each index with indicator
+        let result: Vec<AssignedValue<F>> = (0..vectors[0].len())
+            .map(|i| {
+                self.fixed_point_gate.gate().select_by_indicator(
+                    ctx,
+                    vectors.iter().map(|d| d[i]),
+                    min_indicator.iter().copied(),
+                )
+            })
+            .collect();
+
+        (min_indicator, result)
+    }
+
+    fn top_k<const K: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        query: &Vec<AssignedValue<F>>,
+        vectors: &Vec<Vec<AssignedValue<F>>>,
+        metadata: &Vec<Vec<AssignedValue<F>>>,
+        distance: &dyn Fn(
+            &mut Context<F>,
+            &Vec<AssignedValue<F>>,
+            &Vec<AssignedValue<F>>,
+        ) -> AssignedValue<F>,
+    ) -> [SearchResult<F>; K]
+    where
+        F: ScalarField,
+    {
+        // compute distance to each vector, which must not tie with the selected ones
+        let distances: Vec<AssignedValue<F>> = vectors
+            .iter()
+            .map(|v| {
+                let d = distance(ctx, v, query);
+                self.assert_below_sentinel(ctx, d);
+                d
+            })
+            .collect();
+
+        self.top_k_by_distance::<K>(ctx, distances, vectors, metadata)
+    }
+
+    fn nearest_vector_with_metadata(
+        &self,
+        ctx: &mut Context<F>,
+        query: &Vec<AssignedValue<F>>,
+        vectors: &Vec<Vec<AssignedValue<F>>>,
+        metadata: &Vec<Vec<AssignedValue<F>>>,
+        distance: &dyn Fn(
+            &mut Context<F>,
+            &Vec<AssignedValue<F>>,
+            &Vec<AssignedValue<F>>,
+        ) -> AssignedValue<F>,
+    ) -> SearchResult<F>
+    where
+        F: ScalarField,
+    {
+        let [result] = self.top_k::<1>(ctx, query, vectors, metadata, distance);
+        result
+    }
+
+    fn top_k_with_length<const K: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        query: &Vec<AssignedValue<F>>,
+        vectors: &Vec<Vec<AssignedValue<F>>>,
+        metadata: &Vec<Vec<AssignedValue<F>>>,
+        len: AssignedValue<F>,
+        distance: &dyn Fn(
+            &mut Context<F>,
+            &Vec<AssignedValue<F>>,
+            &Vec<AssignedValue<F>>,
+        ) -> AssignedValue<F>,
+    ) -> [SearchResult<F>; K]
+    where
+        F: ScalarField,
+    {
+        let gate = self.fixed_point_gate.gate();
+        let mask = self.length_mask(ctx, len, vectors.len());
+
+        // mask the distances of the padding slots
+        let distances: Vec<AssignedValue<F>> = vectors
+            .iter()
+            .zip(mask.iter())
+            .map(|(v, m)| {
+                let d = distance(ctx, v, query);
+                self.assert_below_sentinel(ctx, d);
+                gate.select(ctx, d, Constant(self.sentinel()), *m)
+            })
+            .collect();
+
+        let results = self.top_k_by_distance::<K>(ctx, distances, vectors, metadata);
+
+        // each result must be an active slot that is not selected before, as the selected
+        // ones are masked to the sentinel as well
+        let mut available = mask;
+        for result in results.iter() {
+            let is_available =
+                gate.select_by_indicator(ctx, available.clone(), result.indicator.iter().copied());
+            gate.assert_is_const(ctx, &is_available, &F::one());
+            available = available
+                .iter()
+                .zip(result.indicator.iter())
+                .map(|(a, s)| gate.sub(ctx, *a, *s))
+                .collect();
+        }
+
+        results
+    }
+
+    fn nearest_vector_with_length(
+        &self,
+        ctx: &mut Context<F>,
+        query: &Vec<AssignedValue<F>>,
+        vectors: &Vec<Vec<AssignedValue<F>>>,
+        len: AssignedValue<F>,
+        distance: &dyn Fn(
+            &mut Context<F>,
+            &Vec<AssignedValue<F>>,
+            &Vec<AssignedValue<F>>,
+        ) -> AssignedValue<F>,
+    ) -> (Vec<AssignedValue<F>>, Vec<AssignedValue<F>>)
+    where
+        F: ScalarField,
+    {
+        let no_metadata = vec![vec![]; vectors.len()];
+        let [result] =
+            self.top_k_with_length::<1>(ctx, query, vectors, &no_metadata, len, distance);
+        (result.indicator, result.vector)
+    }
+
+    fn filtered_nearest_vector(
+        &self,
+        ctx: &mut Context<F>,
+        query: &Vec<AssignedValue<F>>,
+        vectors: &Vec<Vec<AssignedValue<F>>>,
+        attributes: &Vec<Vec<AssignedValue<F>>>,
+        conditions: &[Condition<F>],
+        distance: &dyn Fn(
+            &mut Context<F>,
+            &Vec<AssignedValue<F>>,
+            &Vec<AssignedValue<F>>,
+        ) -> AssignedValue<F>,
+    ) -> (SearchResult<F>, AssignedValue<F>)
+    where
+        F: ScalarField,
+    {
+        assert_eq!(vectors.len(), attributes.len(), "expected attributes for each vector");
+        let gate = self.fixed_point_gate.gate();
+
+        // mask the distances of the vectors that do not match
+        let matches: Vec<AssignedValue<F>> =
+            attributes.iter().map(|a| self.satisfies(ctx, a, conditions)).collect();
+        let distances: Vec<AssignedValue<F>> = vectors
+            .iter()
+            .zip(matches.iter())
+            .map(|(v, m)| {
+                let d = distance(ctx, v, query);
+                self.assert_below_sentinel(ctx, d);
+                gate.select(ctx, d, Constant(self.sentinel()), *m)
+            })
+            .collect();
+
+        let [result] = self.top_k_by_distance::<1>(ctx, distances, vectors, attributes);
+
+        // the result matches only if some vector does, as matching ones are closer
+        let found = gate.select_by_indicator(ctx, matches, result.indicator.iter().copied());
+
+        (result, found)
+    }
+
+    fn range_query<const T: usize, const RATE: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        poseidon: &mut PoseidonChip<F, T, RATE>,
+        scheme: CommitmentScheme,
+        query: &Vec<AssignedValue<F>>,
+        vectors: &Vec<Vec<AssignedValue<F>>>,
+        threshold: AssignedValue<F>,
+        distance: &dyn Fn(
+            &mut Context<F>,
+            &Vec<AssignedValue<F>>,
+            &Vec<AssignedValue<F>>,
+        ) -> AssignedValue<F>,
+    ) -> RangeQueryResult<F>
+    where
+        F: ScalarField,
+    {
+        let gate = self.fixed_point_gate.gate();
+
+        // a vector is a member if its distance is not greater than the threshold
+        let members: Vec<AssignedValue<F>> = vectors
+            .iter()
+            .map(|v| {
+                let d = distance(ctx, v, query);
+                let diff = self.fixed_point_gate.qsub(ctx, threshold, d);
+                let outside = self.fixed_point_gate.is_neg(ctx, diff);
+                gate.not(ctx, outside)
+            })
+            .collect();
+        let count = gate.sum(ctx, members.iter().copied());
+
+        // commit to the matches, keeping their positions within the database
+        let padding = self.padding_leaf(ctx, poseidon, scheme);
+        let leaves: Vec<AssignedValue<F>> = vectors
+            .iter()
+            .zip(members.iter())
+            .map(|(v, m)| {
+                let leaf = self.hash_leaf(ctx, poseidon, scheme, v);
+                gate.select(ctx, leaf, padding, *m)
+            })
+            .collect();
+        let tree_root = self.merkle_tree_root(ctx, poseidon, scheme, leaves);
+        let dim = ctx.load_constant(F::from(vectors[0].len() as u64));
+        let commitment = self.bind_root(ctx, poseidon, scheme, tree_root, count, dim);
+
+        RangeQueryResult { members, count, commitment }
+    }
+
+    fn threshold_match<const T: usize, const RATE: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        poseidon: &mut PoseidonChip<F, T, RATE>,
+        scheme: CommitmentScheme,
+        query: &Vec<AssignedValue<F>>,
+        salt: AssignedValue<F>,
+        vectors: &Vec<Vec<AssignedValue<F>>>,
+        threshold: AssignedValue<F>,
+        distance: &dyn Fn(
+            &mut Context<F>,
+            &Vec<AssignedValue<F>>,
+            &Vec<AssignedValue<F>>,
+        ) -> AssignedValue<F>,
+    ) -> ThresholdMatch<F>
+    where
+        F: ScalarField,
+    {
+        let gate = self.fixed_point_gate.gate();
+
+        // some vector is within the threshold iff the closest one is
+        let min: AssignedValue<F> = vectors
+            .iter()
+            .map(|v| distance(ctx, v, query))
+            .collect::<Vec<_>>()
+            .into_iter()
+            .reduce(|min, d| self.fixed_point_gate.qmin(ctx, min, d))
+            .unwrap();
+        let diff = self.fixed_point_gate.qsub(ctx, threshold, min);
+        let outside = self.fixed_point_gate.is_neg(ctx, diff);
+        let matched = gate.not(ctx, outside);
+
+        let database_root = self.merkle_commitment_with_scheme(ctx, poseidon, scheme, vectors);
+        let query_commitment = self.commit_query(ctx, poseidon, query, salt);
+
+        ThresholdMatch { database_root, query_commitment, matched }
+    }
+
+    fn commit_query<const T: usize, const RATE: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        poseidon: &mut PoseidonChip<F, T, RATE>,
+        query: &Vec<AssignedValue<F>>,
+        salt: AssignedValue<F>,
+    ) -> AssignedValue<F>
+    where
+        F: ScalarField,
+    {
+        self.hash_salted(ctx, poseidon, CommitmentScheme::QUERY_TAG, query, salt)
+    }
+
+    fn public_result<const T: usize, const RATE: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        poseidon: &mut PoseidonChip<F, T, RATE>,
+        visibility: ResultVisibility,
+        result: &Vec<AssignedValue<F>>,
+        salt: AssignedValue<F>,
+    ) -> Vec<AssignedValue<F>>
+    where
+        F: ScalarField,
+    {
+        match visibility {
+            ResultVisibility::Raw => result.clone(),
+            ResultVisibility::Hashed => {
+                vec![self.hash_salted(ctx, poseidon, CommitmentScheme::RESULT_TAG, result, salt)]
+            }
+        }
+    }
+
+    fn batch_nearest_vector<const T: usize, const RATE: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        poseidon: &mut PoseidonChip<F, T, RATE>,
+        scheme: CommitmentScheme,
+        queries: &Vec<Vec<AssignedValue<F>>>,
+        vectors: &Vec<Vec<AssignedValue<F>>>,
+        precompute: &dyn Fn(&mut Context<F>, &Vec<AssignedValue<F>>) -> AssignedValue<F>,
+        distance: &dyn Fn(
+            &mut Context<F>,
+            &Vec<AssignedValue<F>>,
+            AssignedValue<F>,
+            &Vec<AssignedValue<F>>,
+            AssignedValue<F>,
+        ) -> AssignedValue<F>,
+    ) -> BatchSearchResult<F>
+    where
+        F: ScalarField,
+    {
+        let gate = self.fixed_point_gate.gate();
+
+        // shared across all queries
+        let database_root = self.merkle_commitment_with_scheme(ctx, poseidon, scheme, vectors);
+        let vectors_pre: Vec<AssignedValue<F>> =
+            vectors.iter().map(|v| precompute(ctx, v)).collect();
+
+        let mut indicators = Vec::with_capacity(queries.len());
+        let mut results = Vec::with_capacity(queries.len());
+        for query in queries.iter() {
+            let query_pre = precompute(ctx, query);
+            let distances: Vec<AssignedValue<F>> = vectors
+                .iter()
+                .zip(vectors_pre.iter())
+                .map(|(v, v_pre)| distance(ctx, v, *v_pre, query, query_pre))
+                .collect();
+
+            let (_, indicator) = self.argmin(ctx, &distances);
+            let result: Vec<AssignedValue<F>> = (0..vectors[0].len())
+                .map(|i| {
+                    gate.select_by_indicator(
+                        ctx,
+                        vectors.iter().map(|v| v[i]),
+                        indicator.iter().copied(),
+                    )
+                })
+                .collect();
+
+            indicators.push(indicator);
+            results.push(result);
+        }
+
+        BatchSearchResult { database_root, indicators, results }
+    }
+
+    fn ivf_commitment<const T: usize, const RATE: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        poseidon: &mut PoseidonChip<F, T, RATE>,
+        scheme: CommitmentScheme,
+        centroids: &Vec<Vec<AssignedValue<F>>>,
+        cluster_roots: &Vec<AssignedValue<F>>,
+    ) -> AssignedValue<F>
+    where
+        F: ScalarField,
+    {
+        assert_eq!(centroids.len(), cluster_roots.len(), "expected a root for each cluster");
+        let metadata: Vec<Vec<AssignedValue<F>>> = cluster_roots.iter().map(|r| vec![*r]).collect();
+        self.merkle_commitment_with_metadata(ctx, poseidon, scheme, centroids, &metadata)
+    }
+
+    fn ivf_query<const T: usize, const RATE: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        poseidon: &mut PoseidonChip<F, T, RATE>,
+        scheme: CommitmentScheme,
+        query: &Vec<AssignedValue<F>>,
+        centroids: &Vec<Vec<AssignedValue<F>>>,
+        cluster_roots: &Vec<AssignedValue<F>>,
+        cluster: &Vec<Vec<AssignedValue<F>>>,
+        distance: &dyn Fn(
+            &mut Context<F>,
+            &Vec<AssignedValue<F>>,
+            &Vec<AssignedValue<F>>,
+        ) -> AssignedValue<F>,
+    ) -> IvfQueryResult<F>
+    where
+        F: ScalarField,
+    {
+        let ivf = self.ivf_query_multi_probe::<1, T, RATE>(
+            ctx,
+            poseidon,
+            scheme,
+            query,
+            centroids,
+            cluster_roots,
+            &[cluster.clone()],
+            distance,
+        );
+        let [centroid_indicator] = ivf.centroid_indicators;
+        let [cluster_root] = ivf.cluster_roots;
+
+        IvfQueryResult {
+            ivf_root: ivf.ivf_root,
+            centroid_indicator,
+            cluster_root,
+            indicator: ivf.indicator,
+            result: ivf.result,
+        }
+    }
+
+    fn ivf_query_multi_probe<const P: usize, const T: usize, const RATE: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        poseidon: &mut PoseidonChip<F, T, RATE>,
+        scheme: CommitmentScheme,
+        query: &Vec<AssignedValue<F>>,
+        centroids: &Vec<Vec<AssignedValue<F>>>,
+        cluster_roots: &Vec<AssignedValue<F>>,
+        clusters: &[Vec<Vec<AssignedValue<F>>>; P],
+        distance: &dyn Fn(
+            &mut Context<F>,
+            &Vec<AssignedValue<F>>,
+            &Vec<AssignedValue<F>>,
+        ) -> AssignedValue<F>,
+    ) -> IvfMultiProbeResult<F, P>
+    where
+        F: ScalarField,
+    {
+        assert!(
+            clusters.iter().any(|cluster| !cluster.is_empty()),
+            "expected a non-empty cluster among the probed ones"
+        );
+        let ivf_root = self.ivf_commitment(ctx, poseidon, scheme, centroids, cluster_roots);
+
+        // the nearest centroids select their cluster roots
+        let metadata: Vec<Vec<AssignedValue<F>>> = cluster_roots.iter().map(|r| vec![*r]).collect();
+        let probes = self.top_k::<P>(ctx, query, centroids, &metadata, distance);
+        let cluster_roots: [AssignedValue<F>; P] = core::array::from_fn(|i| probes[i].metadata[0]);
+
+        // the given clusters must be the selected ones
+        for (cluster, cluster_root) in clusters.iter().zip(cluster_roots.iter()) {
+            let root = if cluster.is_empty() {
+                ctx.load_zero()
+            } else {
+                self.merkle_commitment_with_scheme(ctx, poseidon, scheme, cluster)
+            };
+            ctx.constrain_equal(&root, cluster_root);
+        }
+
+        // search within the union of the clusters
+        let union: Vec<Vec<AssignedValue<F>>> = clusters.iter().flatten().cloned().collect();
+        let no_metadata = vec![vec![]; union.len()];
+        let nearest = self.nearest_vector_with_metadata(ctx, query, &union, &no_metadata, distance);
+
+        IvfMultiProbeResult {
+            ivf_root,
+            centroid_indicators: probes.map(|probe| probe.indicator),
+            cluster_roots,
+            indicator: nearest.indicator,
+            result: nearest.vector,
+        }
+    }
+
+    fn merkle_commitment<const T: usize, const RATE: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        poseidon: &mut PoseidonChip<F, T, RATE>,
+        vectors: &Vec<Vec<AssignedValue<F>>>,
+    ) -> AssignedValue<F>
+    where
+        F: ScalarField,
+    {
+        self.merkle_commitment_with_scheme(ctx, poseidon, CommitmentScheme::Plain, vectors)
+    }
+
+    fn merkle_commitment_with_scheme<const T: usize, const RATE: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        poseidon: &mut PoseidonChip<F, T, RATE>,
+        scheme: CommitmentScheme,
+        vectors: &Vec<Vec<AssignedValue<F>>>,
+    ) -> AssignedValue<F>
+    where
+        F: ScalarField,
+    {
+        // hash each vector to a field element
+        // this is okay to do because we dont care about elements of vectors
+        // we just want to commit to an entire vector, or none at all
+        let hashes: Vec<AssignedValue<F>> =
+            vectors.iter().map(|v| self.hash_leaf(ctx, poseidon, scheme, v)).collect();
+
+        let tree_root = self.merkle_tree_root(ctx, poseidon, scheme, hashes);
+
+        let len = ctx.load_constant(F::from(vectors.len() as u64));
+        let dim = ctx.load_constant(F::from(vectors[0].len() as u64));
+        self.bind_root(ctx, poseidon, scheme, tree_root, len, dim)
+    }
+
+    fn merkle_commitment_with_metadata<const T: usize, const RATE: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        poseidon: &mut PoseidonChip<F, T, RATE>,
+        scheme: CommitmentScheme,
+        vectors: &Vec<Vec<AssignedValue<F>>>,
+        metadata: &Vec<Vec<AssignedValue<F>>>,
+    ) -> AssignedValue<F>
+    where
+        F: ScalarField,
+    {
+        assert_eq!(vectors.len(), metadata.len(), "expected metadata for each vector");
+
+        let hashes: Vec<AssignedValue<F>> = vectors
+            .iter()
+            .zip(metadata.iter())
+            .map(|(v, m)| self.hash_record(ctx, poseidon, scheme, v, m))
+            .collect();
+
+        let tree_root = self.merkle_tree_root(ctx, poseidon, scheme, hashes);
+
+        let len = ctx.load_constant(F::from(vectors.len() as u64));
+        let dim = ctx.load_constant(F::from(vectors[0].len() as u64));
+        self.bind_root(ctx, poseidon, scheme, tree_root, len, dim)
+    }
+
+    fn merkle_commitment_with_length<const T: usize, const RATE: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        poseidon: &mut PoseidonChip<F, T, RATE>,
+        scheme: CommitmentScheme,
+        vectors: &Vec<Vec<AssignedValue<F>>>,
+        len: AssignedValue<F>,
+    ) -> AssignedValue<F>
+    where
+        F: ScalarField,
+    {
+        let gate = self.fixed_point_gate.gate();
+        let mask = self.length_mask(ctx, len, vectors.len());
+
+        // padding slots are hashed as well to keep the circuit fixed, but their leaves are replaced
+        let padding = self.padding_leaf(ctx, poseidon, scheme);
+        let hashes: Vec<AssignedValue<F>> = vectors
+            .iter()
+            .zip(mask.iter())
+            .map(|(v, m)| {
+                let leaf = self.hash_leaf(ctx, poseidon, scheme, v);
+                gate.select(ctx, leaf, padding, *m)
+            })
+            .collect();
+
+        let tree_root = self.merkle_tree_root(ctx, poseidon, scheme, hashes);
+
+        let dim = ctx.load_constant(F::from(vectors[0].len() as u64));
+        self.bind_root(ctx, poseidon, scheme, tree_root, len, dim)
+    }
+
+    fn merkle_commitment_with_params<const T: usize, const RATE: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        params: &CommitmentParams<T, RATE>,
+        vectors: &Vec<Vec<AssignedValue<F>>>,
+    ) -> AssignedValue<F>
+    where
+        F: ScalarField,
+    {
+        let mut poseidon = params.poseidon_chip(ctx);
+        self.merkle_commitment_with_scheme(ctx, &mut poseidon, params.scheme, vectors)
+    }
+
+    fn keccak_merkle_commitment(
+        &self,
+        ctx: &mut Context<F>,
+        keccak: &mut KeccakChip<F>,
+        vectors: &Vec<Vec<AssignedValue<F>>>,
+    ) -> Vec<AssignedValue<F>>
+    where
+        F: ScalarField,
+    {
+        let mut leaves: Vec<Vec<AssignedValue<F>>> =
+            vectors.iter().map(|v| self.keccak_leaf(ctx, keccak, v)).collect();
+
+        // pad with zero leaves to a power of two
+        let num_leaves = leaves.len().next_power_of_two();
+        if num_leaves > leaves.len() {
+            let padding = vec![ctx.load_zero(); 32];
+            leaves.resize(num_leaves, padding);
+        }
+
+        while leaves.len() > 1 {
+            leaves = leaves
+                .chunks(2)
+                .map(|children| {
+                    let bytes = children.concat();
+                    self.keccak_bytes(ctx, keccak, bytes)
+                })
+                .collect();
+        }
+
+        leaves.pop().unwrap()
+    }
+
+    fn verify_merkle_path<const T: usize, const RATE: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        poseidon: &mut PoseidonChip<F, T, RATE>,
+        root: AssignedValue<F>,
+        vector: &Vec<AssignedValue<F>>,
+        index: AssignedValue<F>,
+        siblings: &Vec<AssignedValue<F>>,
+    ) where
+        F: ScalarField,
+    {
+        // hash the vector to a leaf, same as in the commitment
+        let leaf = self.hash_leaf(ctx, poseidon, CommitmentScheme::Plain, vector);
+
+        let computed_root =
+            self.merkle_path_root(ctx, poseidon, CommitmentScheme::Plain, leaf, index, siblings);
+        ctx.constrain_equal(&computed_root, &root);
+    }
+
+    fn verify_merkle_path_with_scheme<const T: usize, const RATE: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        poseidon: &mut PoseidonChip<F, T, RATE>,
+        scheme: CommitmentScheme,
+        root: AssignedValue<F>,
+        len: AssignedValue<F>,
+        vector: &Vec<AssignedValue<F>>,
+        index: AssignedValue<F>,
+        siblings: &Vec<AssignedValue<F>>,
+    ) where
+        F: ScalarField,
+    {
+        let leaf = self.hash_leaf(ctx, poseidon, scheme, vector);
+        let tree_root = self.merkle_path_root(ctx, poseidon, scheme, leaf, index, siblings);
+
+        let dim = ctx.load_constant(F::from(vector.len() as u64));
+        let computed_root = self.bind_root(ctx, poseidon, scheme, tree_root, len, dim);
+        ctx.constrain_equal(&computed_root, &root);
+    }
+
+    fn merkle_update<const T: usize, const RATE: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        poseidon: &mut PoseidonChip<F, T, RATE>,
+        scheme: CommitmentScheme,
+        update: MerkleUpdate,
+        old_root: AssignedValue<F>,
+        len: AssignedValue<F>,
+        index: AssignedValue<F>,
+        siblings: &Vec<AssignedValue<F>>,
+        old_vector: &Vec<AssignedValue<F>>,
+        new_vector: &Vec<AssignedValue<F>>,
+    ) -> (AssignedValue<F>, AssignedValue<F>)
+    where
+        F: ScalarField,
+    {
+        let (old_leaf, new_leaf, new_len, dim) = match update {
+            MerkleUpdate::Insert => {
+                // the first empty slot is right after the last vector, which is only meaningful
+                // if the old root binds `len`, otherwise any padding slot could be claimed
+                assert_eq!(
+                    scheme,
+                    CommitmentScheme::DomainSeparated,
+                    "expected the domain-separated scheme for insertions"
+                );
+                ctx.constrain_equal(&index, &len);
+                let old_leaf = self.padding_leaf(ctx, poseidon, scheme);
+                let new_leaf = self.hash_leaf(ctx, poseidon, scheme, new_vector);
+                let new_len = self.fixed_point_gate.gate().add(ctx, len, Constant(F::one()));
+                (old_leaf, new_leaf, new_len, new_vector.len())
+            }
+            MerkleUpdate::Replace => {
+                assert_eq!(old_vector.len(), new_vector.len(), "expected same dimensions");
+                let old_leaf = self.hash_leaf(ctx, poseidon, scheme, old_vector);
+                let new_leaf = self.hash_leaf(ctx, poseidon, scheme, new_vector);
+                (old_leaf, new_leaf, len, new_vector.len())
+            }
+            MerkleUpdate::Delete => {
+                let old_leaf = self.hash_leaf(ctx, poseidon, scheme, old_vector);
+                let new_leaf = self.tombstone_leaf(ctx, poseidon);
+                (old_leaf, new_leaf, len, old_vector.len())
+            }
+        };
+        let dim = ctx.load_constant(F::from(dim as u64));
+
+        // the old leaf must be within the old root
+        let old_tree_root = self.merkle_path_root(ctx, poseidon, scheme, old_leaf, index, siblings);
+        let computed_old_root = self.bind_root(ctx, poseidon, scheme, old_tree_root, len, dim);
+        ctx.constrain_equal(&computed_old_root, &old_root);
 
-    pub fn default(fixed_point_gate: &'a FixedPointChip<F, PRECISION_BITS>) -> Self {
-        Self::new(VectorDBStrategy::Vertical, fixed_point_gate)
+        // the new leaf has the same path
+        let new_tree_root = self.merkle_path_root(ctx, poseidon, scheme, new_leaf, index, siblings);
+        let new_root = self.bind_root(ctx, poseidon, scheme, new_tree_root, new_len, dim);
+
+        (new_root, new_len)
     }
-}
 
-pub trait VectorDBInstructions<F: ScalarField, const PRECISION_BITS: u32> {
-    type FixedPointGate: FixedPointInstructions<F, PRECISION_BITS>;
+    fn merkle_append<const T: usize, const RATE: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        poseidon: &mut PoseidonChip<F, T, RATE>,
+        scheme: CommitmentScheme,
+        old_root: AssignedValue<F>,
+        len: AssignedValue<F>,
+        frontier: &Vec<AssignedValue<F>>,
+        vectors: &Vec<Vec<AssignedValue<F>>>,
+    ) -> (AssignedValue<F>, AssignedValue<F>, Vec<AssignedValue<F>>)
+    where
+        F: ScalarField,
+    {
+        assert!(!vectors.is_empty(), "expected at least one vector");
+        assert_eq!(RATE, 2, "expected a binary tree");
+        let gate = self.fixed_point_gate.gate();
+        let depth = frontier.len();
+        let dim = ctx.load_constant(F::from(vectors[0].len() as u64));
 
-    fn fixed_point_gate(&self) -> &Self::FixedPointGate;
+        // roots of empty subtrees at each level
+        let mut zeros = vec![self.padding_leaf(ctx, poseidon, scheme)];
+        for level in 0..depth {
+            let zero = zeros[level];
+            zeros.push(self.hash_node(ctx, poseidon, scheme, &[zero, zero]));
+        }
 
-    fn strategy(&self) -> VectorDBStrategy;
+        // the frontier must be consistent with the old root, where the path of the next
+        // empty slot goes right when the bit of `len` is set, and left over an empty subtree otherwise
+        let bits = gate.num_to_bits(ctx, len, depth);
+        let mut node = zeros[0];
+        for level in 0..depth {
+            let left = gate.select(ctx, frontier[level], node, bits[level]);
+            let right = gate.select(ctx, node, zeros[level], bits[level]);
+            node = self.hash_node(ctx, poseidon, scheme, &[left, right]);
+        }
+        let computed_old_root = self.bind_root(ctx, poseidon, scheme, node, len, dim);
+        ctx.constrain_equal(&computed_old_root, &old_root);
 
-    /// Given a `query` vector, returns the most similar vector
-    /// by doing an exhaustive search over all the given `vectors`
-    /// and with respect to provided `distance` function.
-    ///
-    /// Returns the closest (most similar) vector along with a minimum indicator
-    /// that is 1 at the index of the vector, and 0 on all other places.
-    fn nearest_vector(
+        // append each vector, updating the frontier along the way
+        let mut frontier = frontier.clone();
+        let mut len = len;
+        let mut tree_root = node;
+        for vector in vectors {
+            // this also constrains the tree to have an empty slot
+            let bits = gate.num_to_bits(ctx, len, depth);
+
+            let mut node = self.hash_leaf(ctx, poseidon, scheme, vector);
+            for level in 0..depth {
+                // the node is on the left if the bit is not set, and it becomes the frontier
+                frontier[level] = gate.select(ctx, frontier[level], node, bits[level]);
+                let left = frontier[level];
+                let right = gate.select(ctx, node, zeros[level], bits[level]);
+                node = self.hash_node(ctx, poseidon, scheme, &[left, right]);
+            }
+
+            tree_root = node;
+            len = gate.add(ctx, len, Constant(F::one()));
+        }
+
+        let new_root = self.bind_root(ctx, poseidon, scheme, tree_root, len, dim);
+        (new_root, len, frontier)
+    }
+
+    fn verify_sparse_inclusion<const T: usize, const RATE: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        poseidon: &mut PoseidonChip<F, T, RATE>,
+        scheme: CommitmentScheme,
+        root: AssignedValue<F>,
+        id: AssignedValue<F>,
+        vector: &Vec<AssignedValue<F>>,
+        metadata: &Vec<AssignedValue<F>>,
+        siblings: &Vec<AssignedValue<F>>,
+    ) where
+        F: ScalarField,
+    {
+        let leaf = self.hash_entry(ctx, poseidon, scheme, id, vector, metadata);
+        let computed_root = self.merkle_path_root(ctx, poseidon, scheme, leaf, id, siblings);
+        ctx.constrain_equal(&computed_root, &root);
+    }
+
+    fn verify_sparse_non_inclusion<const T: usize, const RATE: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        poseidon: &mut PoseidonChip<F, T, RATE>,
+        scheme: CommitmentScheme,
+        root: AssignedValue<F>,
+        id: AssignedValue<F>,
+        siblings: &Vec<AssignedValue<F>>,
+    ) where
+        F: ScalarField,
+    {
+        let leaf = self.padding_leaf(ctx, poseidon, scheme);
+        let computed_root = self.merkle_path_root(ctx, poseidon, scheme, leaf, id, siblings);
+        ctx.constrain_equal(&computed_root, &root);
+    }
+
+    fn kmeans<const K: usize, const I: usize>(
         &self,
         ctx: &mut Context<F>,
-        query: &Vec<AssignedValue<F>>,
         vectors: &Vec<Vec<AssignedValue<F>>>,
         distance: &dyn Fn(
             &mut Context<F>,
             &Vec<AssignedValue<F>>,
             &Vec<AssignedValue<F>>,
         ) -> AssignedValue<F>,
-    ) -> (Vec<AssignedValue<F>>, Vec<AssignedValue<F>>)
+    ) -> KMeansResult<F, K>
     where
-        F: ScalarField;
+        F: ScalarField,
+    {
+        let (_, centroids, cluster_selectors) =
+            self.kmeans_iterations::<K, I>(ctx, vectors, distance, false);
 
-    /// Commits to an array of vectors using Merkle tree with Poseidon hash.
-    ///
-    /// If the given `vectors` does not have power-of-two many elements, it will
-    /// add zeros to the leaves to make up for the remaining leaves.
-    fn merkle_commitment<const T: usize, const RATE: usize>(
+        self.kmeans_result::<K>(ctx, centroids, cluster_selectors)
+    }
+
+    fn kmeans_with_length<const K: usize, const I: usize>(
         &self,
         ctx: &mut Context<F>,
-        poseidon: &mut PoseidonChip<F, T, RATE>,
         vectors: &Vec<Vec<AssignedValue<F>>>,
-    ) -> AssignedValue<F>
+        len: AssignedValue<F>,
+        distance: &dyn Fn(
+            &mut Context<F>,
+            &Vec<AssignedValue<F>>,
+            &Vec<AssignedValue<F>>,
+        ) -> AssignedValue<F>,
+    ) -> KMeansResult<F, K>
     where
-        F: ScalarField;
+        F: ScalarField,
+    {
+        let mask = self.length_mask(ctx, len, vectors.len());
+        let (centroids, cluster_selectors) =
+            self.masked_kmeans::<K, I>(ctx, vectors, &mask, distance);
 
-    /// K-means algorithm to compute `K` centroids from a given set of vectors.
-    /// Since the algorithm can't stop execution based on convergence, we instead
-    /// opt for a fixed-iteration approach.
-    ///
-    /// - K: number of centroids
-    /// - I: number of iterations
-    ///
-    /// Returns the centroids and cluster indicators for each vector (one-hot encoded).
-    fn kmeans<const K: usize, const I: usize>(
+        self.kmeans_result::<K>(ctx, centroids, cluster_selectors)
+    }
+
+    fn spherical_kmeans<const K: usize, const I: usize>(
         &self,
         ctx: &mut Context<F>,
         vectors: &Vec<Vec<AssignedValue<F>>>,
@@ -85,144 +2706,169 @@ pub trait VectorDBInstructions<F: ScalarField, const PRECISION_BITS: u32> {
             &Vec<AssignedValue<F>>,
             &Vec<AssignedValue<F>>,
         ) -> AssignedValue<F>,
-    ) -> ([Vec<AssignedValue<F>>; K], Vec<[AssignedValue<F>; K]>)
+    ) -> KMeansResult<F, K>
     where
-        F: ScalarField;
-
-    // Given a set of vectors and a mean vector, asserts that the mean of these vectors result in that
-    // given vector; and returns a Merkle commitment to the given vectors.
-    //
-    // This is mostly used for cluster commitments at the end of K-means.
-    //
-    // TODO: It is possible for an adversary to construct another set of vectors that may result in the centroid
-    // fn mean_merkle<const T: usize, const RATE: usize>(
-    //     &self,
-    //     ctx: &mut Context<F>,
-    //     poseidon: &mut PoseidonChip<F, T, RATE>,
-    //     vectors: &Vec<Vec<AssignedValue<F>>>,
-    //     expected_mean: &Vec<AssignedValue<F>>,
-    // ) -> AssignedValue<F>
-    // where
-    //     F: ScalarField;
-}
-
-impl<'a, F: ScalarField, const PRECISION_BITS: u32> VectorDBInstructions<F, PRECISION_BITS>
-    for VectorDBChip<'a, F, PRECISION_BITS>
-{
-    type FixedPointGate = FixedPointChip<F, PRECISION_BITS>;
-
-    fn fixed_point_gate(&self) -> &Self::FixedPointGate {
-        &self.fixed_point_gate
-    }
+        F: ScalarField,
+    {
+        let (_, centroids, cluster_selectors) =
+            self.kmeans_iterations::<K, I>(ctx, vectors, distance, true);
 
-    fn strategy(&self) -> VectorDBStrategy {
-        self.strategy
+        self.kmeans_result::<K>(ctx, centroids, cluster_selectors)
     }
 
-    fn nearest_vector(
+    fn hierarchical_kmeans<const B: usize, const I: usize, const T: usize, const RATE: usize>(
         &self,
         ctx: &mut Context<F>,
-        query: &Vec<AssignedValue<F>>,
+        poseidon: &mut PoseidonChip<F, T, RATE>,
+        scheme: CommitmentScheme,
         vectors: &Vec<Vec<AssignedValue<F>>>,
+        depth: usize,
         distance: &dyn Fn(
             &mut Context<F>,
             &Vec<AssignedValue<F>>,
             &Vec<AssignedValue<F>>,
         ) -> AssignedValue<F>,
-    ) -> (Vec<AssignedValue<F>>, Vec<AssignedValue<F>>)
+    ) -> HierarchicalKMeansResult<F>
     where
         F: ScalarField,
     {
-        // compute distance to each vector
-        let distances: Vec<AssignedValue<F>> =
-            vectors.iter().map(|v| distance(ctx, v, query)).collect();
+        assert!(depth > 0, "expected a positive depth");
+        assert!(B < vectors.len());
+        let one = ctx.load_constant(F::one());
 
-        // find the minimum
-        let min: AssignedValue<F> = distances
-            .clone()
-            .into_iter()
-            .reduce(|acc, d| self.fixed_point_gate.qmin(ctx, acc, d))
-            .unwrap();
-        let min_indicator: Vec<AssignedValue<F>> = distances
-            .into_iter()
-            .map(|d| self.fixed_point_gate.gate().is_equal(ctx, min, d))
+        // each cluster is represented by a boolean mask over the vectors,
+        // starting with a single cluster that has all the vectors
+        let mut masks: Vec<Vec<AssignedValue<F>>> = vec![vec![one; vectors.len()]];
+
+        let mut levels: Vec<Vec<Vec<AssignedValue<F>>>> = Vec::with_capacity(depth);
+        for _level in 0..depth {
+            let mut centroids = Vec::with_capacity(masks.len() * B);
+            let mut child_masks = Vec::with_capacity(masks.len() * B);
+
+            for mask in masks.iter() {
+                let (cluster_centroids, cluster_selectors) =
+                    self.masked_kmeans::<B, I>(ctx, vectors, mask, distance);
+
+                centroids.extend(cluster_centroids);
+                child_masks.extend((0..B).map(|b| {
+                    cluster_selectors.iter().map(|selectors| selectors[b]).collect::<Vec<_>>()
+                }));
+            }
+
+            levels.push(centroids);
+            masks = child_masks;
+        }
+
+        // commit to each level
+        let level_roots: Vec<AssignedValue<F>> = levels
+            .iter()
+            .map(|centroids| self.merkle_commitment_with_scheme(ctx, poseidon, scheme, centroids))
             .collect();
 
-        // get the most similar vector by selecting each index with indicator
-        let result: Vec<AssignedValue<F>> = (0..vectors[0].len())
-            .map(|i| {
+        // the masks of the last level indicate the leaf cluster of each vector
+        let cluster_selectors: Vec<Vec<AssignedValue<F>>> =
+            (0..vectors.len()).map(|j| masks.iter().map(|mask| mask[j]).collect()).collect();
+        let cluster_ids: Vec<AssignedValue<F>> = cluster_selectors
+            .iter()
+            .map(|selectors| {
                 self.fixed_point_gate.gate().select_by_indicator(
                     ctx,
-                    vectors.iter().map(|d| d[i]),
-                    min_indicator.iter().copied(),
+                    (0..selectors.len()).map(|i| Constant(F::from(i as u64))),
+                    selectors.iter().copied(),
                 )
             })
             .collect();
 
-        (min_indicator, result)
+        HierarchicalKMeansResult { levels, level_roots, cluster_selectors, cluster_ids }
     }
 
-    fn merkle_commitment<const T: usize, const RATE: usize>(
+    fn kmeans_with_convergence<const K: usize, const I: usize>(
         &self,
         ctx: &mut Context<F>,
-        poseidon: &mut PoseidonChip<F, T, RATE>,
         vectors: &Vec<Vec<AssignedValue<F>>>,
-    ) -> AssignedValue<F>
+        distance: &dyn Fn(
+            &mut Context<F>,
+            &Vec<AssignedValue<F>>,
+            &Vec<AssignedValue<F>>,
+        ) -> AssignedValue<F>,
+    ) -> (KMeansResult<F, K>, KMeansConvergence<F>)
     where
         F: ScalarField,
     {
-        // hash each vector to a field element
-        // this is okay to do because we dont care about elements of vectors
-        // we just want to commit to an entire vector, or none at all
-        let hashes: Vec<AssignedValue<F>> = vectors
+        assert!(I > 0, "expected at least one iteration");
+
+        let (previous_centroids, centroids, cluster_selectors) =
+            self.kmeans_iterations::<K, I>(ctx, vectors, distance, false);
+
+        // squared Euclidean distance between each centroid before & after the final update
+        let movements: Vec<AssignedValue<F>> = previous_centroids
             .iter()
-            .map(|v| {
-                poseidon.clear();
-                poseidon.update(&v.as_slice());
-                poseidon.squeeze(ctx, self.fixed_point_gate.gate()).unwrap()
+            .zip(centroids.iter())
+            .map(|(previous, current)| {
+                let diff: Vec<AssignedValue<F>> = current
+                    .iter()
+                    .zip(previous)
+                    .map(|(c, p)| self.fixed_point_gate.qsub(ctx, *c, *p))
+                    .collect();
+                self.fixed_point_gate.inner_product(ctx, diff.clone(), diff)
             })
             .collect();
+        let movement = self.fixed_point_gate.gate().sum(ctx, movements);
 
-        // extend leaves with zeros to ensure number of leaves is a power of two
-        let num_hashes = hashes.len();
-        let num_leaves: usize = if (num_hashes & (num_hashes - 1)) == 0 {
-            num_hashes
-        } else {
-            let mut next_pow_of_two = 1 as usize;
-            while next_pow_of_two < num_hashes {
-                next_pow_of_two <<= 1;
-            }
-            next_pow_of_two
-        };
-        assert!(num_hashes <= num_leaves, "expected #hashes to be less than computed #leaves");
-        let num_zeros = num_leaves - num_hashes;
+        let objective =
+            self.kmeans_objective::<K>(ctx, vectors, &centroids, &cluster_selectors, distance);
 
-        // construct merklee tree from the hashes & zeros
-        let mut leaves: Vec<AssignedValue<F>> = hashes;
-        if num_zeros > 0 {
-            leaves.extend(vec![ctx.load_zero(); num_zeros])
-        }
-        assert_eq!(leaves.len(), num_leaves, "expected #leaves many leaves");
+        let result = self.kmeans_result::<K>(ctx, centroids, cluster_selectors);
+        (result, KMeansConvergence { objective, movement })
+    }
 
-        while leaves.len() > 1 {
-            // assert that the number of leaves is always a power of two
-            assert!((leaves.len() & (leaves.len() - 1)) == 0);
-
-            let mut next_leaves = Vec::with_capacity(leaves.len() / 2);
-            for i in (0..leaves.len()).step_by(2) {
-                poseidon.clear();
-                poseidon.update(&[leaves[i], leaves[i + 1]]);
-                next_leaves.push(poseidon.squeeze(ctx, self.fixed_point_gate.gate()).unwrap());
-            }
-            leaves = next_leaves;
-        }
+    fn kmeans_objective<const K: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        vectors: &Vec<Vec<AssignedValue<F>>>,
+        centroids: &[Vec<AssignedValue<F>>; K],
+        cluster_selectors: &Vec<[AssignedValue<F>; K]>,
+        distance: &dyn Fn(
+            &mut Context<F>,
+            &Vec<AssignedValue<F>>,
+            &Vec<AssignedValue<F>>,
+        ) -> AssignedValue<F>,
+    ) -> AssignedValue<F>
+    where
+        F: ScalarField,
+    {
+        assert_eq!(vectors.len(), cluster_selectors.len());
 
-        // we must have reached the root node
-        assert!(leaves.len() == 1);
-        leaves[0]
+        let squared_distances: Vec<AssignedValue<F>> = vectors
+            .iter()
+            .zip(cluster_selectors)
+            .map(|(v, selectors)| {
+                // select the centroid that this vector is assigned to
+                let centroid: Vec<AssignedValue<F>> = (0..v.len())
+                    .map(|i| {
+                        self.fixed_point_gate.gate().select_by_indicator(
+                            ctx,
+                            centroids.iter().map(|c| c[i]),
+                            selectors.iter().copied(),
+                        )
+                    })
+                    .collect();
+
+                let d = distance(ctx, &centroid, v);
+                self.fixed_point_gate.qmul(ctx, d, d)
+            })
+            .collect();
+
+        // quantized values can be summed as field elements, and since each of them is
+        // non-negative the sum is out of range exactly when it looks negative
+        let objective = self.fixed_point_gate.gate().sum(ctx, squared_distances);
+        let is_out_of_range = self.fixed_point_gate.is_neg(ctx, objective);
+        self.fixed_point_gate.gate().assert_is_const(ctx, &is_out_of_range, &F::zero());
+
+        objective
     }
 
-    fn kmeans<const K: usize, const I: usize>(
+    fn kmedoids<const K: usize, const I: usize>(
         &self,
         ctx: &mut Context<F>,
         vectors: &Vec<Vec<AssignedValue<F>>>,
@@ -231,164 +2877,241 @@ impl<'a, F: ScalarField, const PRECISION_BITS: u32> VectorDBInstructions<F, PREC
             &Vec<AssignedValue<F>>,
             &Vec<AssignedValue<F>>,
         ) -> AssignedValue<F>,
-    ) -> ([Vec<AssignedValue<F>>; K], Vec<[AssignedValue<F>; K]>)
+    ) -> KMedoidsResult<F, K>
     where
         F: ScalarField,
     {
         assert!(K < vectors.len());
-        // ones and zeros needed for indicators
-        let one: AssignedValue<F> = ctx.load_constant(self.fixed_point_gate.quantization(1.0));
-        let zero: AssignedValue<F> = ctx.load_zero(); // quantized zero is equal to native zero
+        let gate = self.fixed_point_gate.gate();
+        let n = vectors.len();
 
-        // take first K vectors as the initial centroids
-        let mut centroids: [Vec<AssignedValue<F>>; K] = vectors
-            .iter()
-            .take(K)
-            .cloned()
-            .collect::<Vec<Vec<AssignedValue<F>>>>()
-            .try_into()
-            .unwrap();
+        let zero = ctx.load_zero();
+        let one = ctx.load_constant(F::one());
+        let sentinel = ctx.load_constant(self.sentinel());
+
+        // compute pairwise distances only once, which is the costly part for most metrics;
+        // the distance is assumed symmetric, and each vector has zero distance to itself
+        let mut distances: Vec<Vec<AssignedValue<F>>> = vec![vec![zero; n]; n];
+        for i in 0..n {
+            for j in (i + 1)..n {
+                let d = distance(ctx, &vectors[i], &vectors[j]);
+                distances[i][j] = d;
+                distances[j][i] = d;
+            }
+        }
+
+        // take first K vectors as the initial medoids
+        let mut medoid_indicators: [Vec<AssignedValue<F>>; K] =
+            core::array::from_fn(|k| (0..n).map(|i| if i == k { one } else { zero }).collect());
 
-        let mut cluster_indicators: Vec<[AssignedValue<F>; K]> = vec![];
+        let mut cluster_selectors: Vec<[AssignedValue<F>; K]> = vec![];
 
         for _iter in 0..I {
-            // assign each vector to closest centroid
-            //
-            // instead of assigning a cluster id to each vector,
-            // we will store an indicator (one-hot encoding) for that cluster
-            // suppose K = 4 and vectors A and B belong to 1, 3 respectively
-            // we would have [0, 1, 0, 0] and [0, 0, 0, 1] as the indicators.
-            cluster_indicators = vectors
+            // assign each vector to the closest medoid, where the distance to a medoid
+            // is selected from the row of that vector with the medoid indicator
+            cluster_selectors = distances
                 .iter()
-                .map(|v| {
-                    // compute distance to centroids
-                    let distances: [AssignedValue<F>; K] =
-                        centroids.clone().map(|c| distance(ctx, &c, v));
-                    // it works when i assign `[one; K];` instead
-
-                    // find the minimum
-                    let min: AssignedValue<F> = distances
-                        .clone()
-                        .into_iter()
-                        .reduce(|min, d| self.fixed_point_gate.qmin(ctx, min, d))
-                        .unwrap();
-
-                    // return indicator
-                    let indicators: [AssignedValue<F>; K] = distances.map(|d| {
-                        // check if distance is the minimum
-                        let eq = self.fixed_point_gate.gate().is_equal(ctx, min, d);
-
-                        // return 1 if so, 0 otherwise
-                        self.fixed_point_gate.gate().select(ctx, one, zero, eq)
-                    });
-
-                    indicators
-                })
-                .collect();
+                .map(|row| {
+                    let medoid_distances: Vec<AssignedValue<F>> = medoid_indicators
+                        .iter()
+                        .map(|indicator| {
+                            gate.select_by_indicator(
+                                ctx,
+                                row.iter().copied(),
+                                indicator.iter().copied(),
+                            )
+                        })
+                        .collect();
 
-            // index-wise summation of indicators will give the cluster sizes
-            // this will be used to take the mean value after computing sum of
-            // vectors within the cluster
-            let cluster_sizes: [AssignedValue<F>; K] = cluster_indicators
-                .clone()
-                .into_iter()
-                .reduce(|sizes, indicators| {
-                    // element-wise addition
-                    sizes
-                        .zip(indicators)
-                        .map(|(size, ind)| self.fixed_point_gate.qadd(ctx, size, ind))
+                    let (_, selectors) = self.argmin(ctx, &medoid_distances);
+                    selectors.try_into().unwrap()
                 })
-                .unwrap();
+                .collect();
 
-            // update centroids by finding the mean vector in each cluster
+            // update medoids by finding the member with the least total distance
+            // to the other members of the cluster
             for cluster_id in 0..K {
-                // the index of indicators for this cluster indicates whether a vector
-                // belongs to that cluster or not
                 let is_in_cluster: Vec<AssignedValue<F>> =
-                    cluster_indicators.iter().map(|indicators| indicators[cluster_id]).collect();
+                    cluster_selectors.iter().map(|selectors| selectors[cluster_id]).collect();
 
-                // multiply each element of all vectors with the indicator
-                // that represent the current cluster
-                let filtered_vectors: Vec<Vec<AssignedValue<F>>> = vectors
-                    .clone()
-                    .into_iter()
-                    .zip(is_in_cluster)
-                    .map(|(vector, sel)| {
-                        // multiply each element of the vector by the current cluster indicator
-                        // use select instead of multiplication here, because we would have to do
-                        // a quantized multiplication instead, which is costly.
-                        //
-                        // note that select itself is either a zero or a quantized one, which is
-                        // not boolean! so, we have to compare it to zero and then use that
-                        // equality as the selector
-                        //
-                        // we will add these values later, and that is alright because `v` is
-                        // already quantized, and a field 0 is equal to a quantized 0
-                        // (note that a quantized 1 is not a field 1)
-
-                        let is_zero = self.fixed_point_gate.gate().is_zero(ctx, sel);
-                        vector
-                            .into_iter()
-                            .map(|v| self.fixed_point_gate.gate().select(ctx, zero, v, is_zero))
-                            .collect()
+                let costs: Vec<AssignedValue<F>> = distances
+                    .iter()
+                    .zip(is_in_cluster.iter())
+                    .map(|(row, is_member)| {
+                        // distances are quantized and selectors are bits, so a plain
+                        // inner product gives the sum of distances to the members
+                        let cost = gate.inner_product(
+                            ctx,
+                            row.iter().copied(),
+                            is_in_cluster.iter().map(|s| Existing(*s)),
+                        );
+
+                        // a member must not tie with the masked non-members
+                        let member_cost = gate.mul(ctx, cost, *is_member);
+                        self.assert_below_sentinel(ctx, member_cost);
+
+                        // a vector outside of the cluster can't be its medoid
+                        gate.select(ctx, cost, sentinel, *is_member)
                     })
                     .collect();
 
-                // mean of vectors in this cluster
-                let mean: Vec<AssignedValue<F>> = filtered_vectors
+                // every cost is the sentinel for an empty cluster, which keeps its medoid
+                let cluster_size = gate.sum(ctx, is_in_cluster.clone());
+                let is_empty = gate.is_zero(ctx, cluster_size);
+
+                let (_, indicator) = self.argmin(ctx, &costs);
+                medoid_indicators[cluster_id] = indicator
                     .into_iter()
-                    // sum everything
-                    .reduce(|sum, vector| {
-                        vector
-                            .into_iter()
-                            .zip(sum)
-                            .map(|(s, v)| self.fixed_point_gate.qadd(ctx, s, v))
-                            .collect()
-                    })
-                    // divide by cluster size
-                    .map(|sum| {
-                        sum.into_iter()
-                            .map(|s| self.fixed_point_gate.qdiv(ctx, s, cluster_sizes[cluster_id]))
-                            .collect()
-                    })
-                    .unwrap();
+                    .zip(medoid_indicators[cluster_id].iter())
+                    .map(|(new, previous)| gate.select(ctx, *previous, new, is_empty))
+                    .collect();
+            }
+        }
+
+        let medoid_indices: [AssignedValue<F>; K] = medoid_indicators.clone().map(|indicator| {
+            gate.select_by_indicator(ctx, (0..n).map(|i| Constant(F::from(i as u64))), indicator)
+        });
+
+        let medoids: [Vec<AssignedValue<F>>; K] = medoid_indicators.clone().map(|indicator| {
+            (0..vectors[0].len())
+                .map(|i| {
+                    gate.select_by_indicator(
+                        ctx,
+                        vectors.iter().map(|v| v[i]),
+                        indicator.iter().copied(),
+                    )
+                })
+                .collect()
+        });
+
+        let cluster_ids = self.cluster_ids::<K>(ctx, &cluster_selectors);
+
+        KMedoidsResult {
+            medoids,
+            medoid_indices,
+            medoid_indicators,
+            cluster_selectors,
+            cluster_ids,
+        }
+    }
+
+    fn mean_merkle<const T: usize, const RATE: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        poseidon: &mut PoseidonChip<F, T, RATE>,
+        scheme: CommitmentScheme,
+        vectors: &Vec<Vec<AssignedValue<F>>>,
+        expected_mean: &Vec<AssignedValue<F>>,
+    ) -> AssignedValue<F>
+    where
+        F: ScalarField,
+    {
+        assert!(!vectors.is_empty(), "expected at least one vector");
+        assert!(vectors.iter().all(|v| v.len() == expected_mean.len()));
+
+        let len = ctx.load_constant(self.fixed_point_gate.quantization(vectors.len() as f64));
+
+        // sum everything & divide by length, which is exactly how K-means finds the mean
+        for (i, expected) in expected_mean.iter().enumerate() {
+            let sum = self.fixed_point_gate.gate().sum(ctx, vectors.iter().map(|v| v[i]));
+            let mean = self.fixed_point_gate.qdiv(ctx, sum, len);
+            ctx.constrain_equal(&mean, expected);
+        }
+
+        // return commitment to vectors
+        self.merkle_commitment_with_scheme(ctx, poseidon, scheme, vectors)
+    }
 
-                // update centroid
-                centroids[cluster_id] = mean;
+    fn assert_cluster_membership(
+        &self,
+        ctx: &mut Context<F>,
+        vectors: &Vec<Vec<AssignedValue<F>>>,
+        selectors: &Vec<AssignedValue<F>>,
+        cluster: &Vec<Vec<AssignedValue<F>>>,
+    ) where
+        F: ScalarField,
+    {
+        assert_eq!(vectors.len(), selectors.len());
+        let gate = self.fixed_point_gate.gate();
+
+        // the selectors are used as coefficients below, which only makes sense for bits
+        for selector in selectors.iter() {
+            gate.assert_bit(ctx, *selector);
+        }
+
+        // the cluster must have as many vectors as the selected ones
+        let count = gate.sum(ctx, selectors.iter().copied());
+        gate.assert_is_const(ctx, &count, &F::from(cluster.len() as u64));
+        if cluster.is_empty() {
+            return;
+        }
+
+        // the k-th selected vector must be equal to the k-th vector in the cluster,
+        // which together with the count above makes the cluster exactly the selected ones
+        let mut rank = ctx.load_zero();
+        for (vector, selector) in vectors.iter().zip(selectors) {
+            let indicator = gate.idx_to_indicator(ctx, rank, cluster.len());
+
+            for (i, v_i) in vector.iter().enumerate() {
+                let c_i = gate.select_by_indicator(
+                    ctx,
+                    cluster.iter().map(|c| c[i]),
+                    indicator.iter().copied(),
+                );
+
+                // this is `c_i` if the vector is selected and `v_i` otherwise,
+                // so it is equal to `v_i` only if both are equal when selected
+                let selected = gate.select(ctx, c_i, *v_i, *selector);
+                ctx.constrain_equal(&selected, v_i);
             }
+
+            rank = gate.add(ctx, rank, *selector);
+        }
+    }
+
+    fn index_commitment<const K: usize, const T: usize, const RATE: usize>(
+        &self,
+        ctx: &mut Context<F>,
+        poseidon: &mut PoseidonChip<F, T, RATE>,
+        scheme: CommitmentScheme,
+        vectors: &Vec<Vec<AssignedValue<F>>>,
+        result: &KMeansResult<F, K>,
+        clusters: &[Vec<Vec<AssignedValue<F>>>; K],
+    ) -> IndexCommitment<F>
+    where
+        F: ScalarField,
+    {
+        assert_eq!(vectors.len(), result.cluster_selectors.len());
+        let gate = self.fixed_point_gate.gate();
+
+        // every vector belongs to exactly one cluster, where the selectors are constrained
+        // to be bits by `assert_cluster_membership` below
+        for selectors in result.cluster_selectors.iter() {
+            let count = gate.sum(ctx, selectors.iter().copied());
+            gate.assert_is_const(ctx, &count, &F::one());
         }
 
-        (centroids, cluster_indicators)
-    }
-
-    // fn mean_merkle<const T: usize, const RATE: usize>(
-    //     &self,
-    //     ctx: &mut Context<F>,
-    //     poseidon: &mut PoseidonChip<F, T, RATE>,
-    //     vectors: &Vec<Vec<AssignedValue<F>>>,
-    //     expected_mean: &Vec<AssignedValue<F>>,
-    // ) -> AssignedValue<F>
-    // where
-    //     F: ScalarField,
-    // {
-    //     let len = ctx.load_constant(self.fixed_point_gate.quantization(vectors.len() as f64));
-
-    //     let mean: Vec<AssignedValue<F>> = vectors
-    //         .clone()
-    //         .into_iter()
-    //         // sum everything
-    //         .reduce(|sum, vector| {
-    //             vector
-    //                 .iter()
-    //                 .zip(sum)
-    //                 .map(|(s, v)| self.fixed_point_gate.qadd(ctx, *s, v))
-    //                 .collect()
-    //         })
-    //         // divide by length
-    //         .map(|sum| &sum.into_iter().map(|s| self.fixed_point_gate.qdiv(ctx, s, len)).collect())
-    //         .unwrap();
-
-    //     // return commitment to vectors
-    //     self.merkle_commitment(ctx, poseidon, vectors)
-    // }
+        // each cluster is exactly its selected vectors, so together they partition the database
+        let cluster_roots: Vec<AssignedValue<F>> = clusters
+            .iter()
+            .enumerate()
+            .map(|(k, cluster)| {
+                let selectors: Vec<AssignedValue<F>> =
+                    result.cluster_selectors.iter().map(|selectors| selectors[k]).collect();
+                self.assert_cluster_membership(ctx, vectors, &selectors, cluster);
+
+                if cluster.is_empty() {
+                    ctx.load_zero()
+                } else {
+                    self.merkle_commitment_with_scheme(ctx, poseidon, scheme, cluster)
+                }
+            })
+            .collect();
+
+        let database_root = self.merkle_commitment_with_scheme(ctx, poseidon, scheme, vectors);
+        let centroids_root =
+            self.merkle_commitment_with_scheme(ctx, poseidon, scheme, &result.centroids.to_vec());
+
+        IndexCommitment { database_root, centroids_root, cluster_roots }
+    }
 }
@@ -3,4 +3,5 @@
 #![allow(incomplete_features)]
 
 pub mod gadget;
+pub mod native;
 pub mod scaffold;
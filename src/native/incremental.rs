@@ -0,0 +1,92 @@
+use halo2_base::utils::ScalarField;
+
+use super::merkle::{bind_root, hash_leaf, hash_node, padding_leaf, poseidon};
+use crate::gadget::vectordb::CommitmentParams;
+
+/// An append-only Merkle tree of fixed `depth` that only keeps its frontier, i.e. the
+/// rightmost left node at each level, instead of the entire tree. The tree is binary, so
+/// `RATE` must be 2.
+///
+/// The root is the same as the root of a [`super::merkle::MerkleTree`] with `2^depth` capacity
+/// over the appended vectors, see [`crate::gadget::vectordb::VectorDBInstructions::merkle_append`]
+/// for the circuit counterpart.
+#[derive(Clone, Debug)]
+pub struct IncrementalMerkleTree<F: ScalarField, const T: usize, const RATE: usize> {
+    /// Rightmost left node at each level, which is the left sibling of the path of the next
+    /// empty slot wherever that path goes right.
+    frontier: Vec<F>,
+    /// Root of an empty subtree at each level, starting with the padding leaf.
+    zeros: Vec<F>,
+    tree_root: F,
+    params: CommitmentParams<T, RATE>,
+    len: usize,
+    dim: usize,
+}
+
+impl<F: ScalarField, const T: usize, const RATE: usize> IncrementalMerkleTree<F, T, RATE> {
+    /// Creates an empty tree of the given `depth` for vectors of dimension `dim`, with the
+    /// commitment `params`.
+    pub fn new(depth: usize, dim: usize, params: &CommitmentParams<T, RATE>) -> Self {
+        assert_eq!(RATE, 2, "expected a binary tree");
+        let poseidon = poseidon(params);
+        let scheme = params.scheme;
+
+        let mut zeros = vec![padding_leaf(&poseidon, scheme)];
+        for level in 0..depth {
+            let zero = zeros[level];
+            zeros.push(hash_node(&poseidon, scheme, &[zero, zero]));
+        }
+        let tree_root = zeros[depth];
+
+        Self { frontier: zeros[..depth].to_vec(), zeros, tree_root, params: *params, len: 0, dim }
+    }
+
+    /// Appends a (quantized) vector into the next empty slot, and returns its index.
+    pub fn append(&mut self, vector: &[F]) -> usize {
+        assert!(self.len < 1 << self.depth(), "tree is full");
+        assert_eq!(vector.len(), self.dim, "expected vector of same dimension");
+        let poseidon = poseidon(&self.params);
+
+        let index = self.len;
+        let mut node = hash_leaf(&poseidon, self.params.scheme, vector);
+        for level in 0..self.depth() {
+            node = if (index >> level) & 1 == 0 {
+                // the node is on the left, which becomes the frontier at this level
+                self.frontier[level] = node;
+                hash_node(&poseidon, self.params.scheme, &[node, self.zeros[level]])
+            } else {
+                hash_node(&poseidon, self.params.scheme, &[self.frontier[level], node])
+            };
+        }
+        self.tree_root = node;
+        self.len += 1;
+
+        index
+    }
+
+    /// Root of the tree, without the number of vectors & their dimension bound to it.
+    pub fn tree_root(&self) -> F {
+        self.tree_root
+    }
+
+    /// Merkle root of the tree, with respect to the commitment scheme.
+    pub fn root(&self) -> F {
+        let poseidon = poseidon(&self.params);
+        bind_root(&poseidon, self.params.scheme, self.tree_root, self.len, self.dim)
+    }
+
+    /// Frontier of the tree, i.e. the rightmost left node at each level starting from the leaves.
+    pub fn frontier(&self) -> &Vec<F> {
+        &self.frontier
+    }
+
+    /// Number of levels above the leaves.
+    pub fn depth(&self) -> usize {
+        self.frontier.len()
+    }
+
+    /// Number of appended vectors.
+    pub fn num_vectors(&self) -> usize {
+        self.len
+    }
+}
@@ -0,0 +1,348 @@
+use halo2_base::utils::ScalarField;
+use snark_verifier_sdk::{snark_verifier::util::hash::Poseidon, NativeLoader};
+
+use crate::gadget::vectordb::{CommitmentParams, CommitmentScheme};
+
+/// Computes the Merkle root over the given quantized `vectors` with [`CommitmentParams::DEFAULT`],
+/// which is equal to the root computed by `merkle_commitment` within a `PoseidonChip` created
+/// with the same parameters.
+pub fn merkle_root<F: ScalarField>(vectors: &[Vec<F>]) -> F {
+    MerkleTree::new(vectors, &CommitmentParams::DEFAULT).root()
+}
+
+/// Computes the commitment to the matches of a range query over the given quantized `vectors`,
+/// same as `range_query` does, where `members` tells which of the vectors are within the threshold.
+pub fn range_commitment<F: ScalarField, const T: usize, const RATE: usize>(
+    vectors: &[Vec<F>],
+    members: &[bool],
+    params: &CommitmentParams<T, RATE>,
+) -> F {
+    assert_eq!(vectors.len(), members.len(), "expected membership of each vector");
+    let poseidon = poseidon(params);
+    let padding = padding_leaf(&poseidon, params.scheme);
+
+    let leaves: Vec<F> = vectors
+        .iter()
+        .zip(members.iter())
+        .map(|(v, m)| if *m { hash_leaf(&poseidon, params.scheme, v.as_slice()) } else { padding })
+        .collect();
+    let tree = MerkleTree::from_leaves(leaves, vectors.len(), params, vectors[0].len());
+
+    let count = members.iter().filter(|m| **m).count();
+    bind_root(&poseidon, params.scheme, tree.tree_root(), count, vectors[0].len())
+}
+
+/// Creates a native Poseidon instance with the given parameters, same as the `PoseidonChip`
+/// created by [`CommitmentParams::poseidon_chip`].
+pub(crate) fn poseidon<F: ScalarField, const T: usize, const RATE: usize>(
+    params: &CommitmentParams<T, RATE>,
+) -> Poseidon<F, F, T, RATE> {
+    Poseidon::new(&NativeLoader, params.r_f, params.r_p)
+}
+
+/// Authentication path of a leaf within a [`MerkleTree`].
+#[derive(Clone, Debug)]
+pub struct MerklePath<F: ScalarField> {
+    /// Index of the leaf within the tree.
+    pub index: usize,
+    /// Siblings of each node on the path from the leaf to the tree root, starting with the
+    /// siblings of the leaf itself. There are `RATE - 1` siblings at each level, in the order
+    /// of their positions.
+    pub siblings: Vec<F>,
+}
+
+/// Hashes `inputs` with the given domain `tag`, or without one if `tag` is `None`.
+pub(crate) fn hash_with_tag<F: ScalarField, const T: usize, const RATE: usize>(
+    poseidon: &Poseidon<F, F, T, RATE>,
+    tag: Option<u64>,
+    inputs: &[F],
+) -> F {
+    let mut poseidon = poseidon.clone();
+    if let Some(tag) = tag {
+        poseidon.update(&[F::from(tag)]);
+    }
+    poseidon.update(inputs);
+    poseidon.squeeze()
+}
+
+/// Hashes a vector to a leaf with respect to the commitment `scheme`.
+pub(crate) fn hash_leaf<F: ScalarField, const T: usize, const RATE: usize>(
+    poseidon: &Poseidon<F, F, T, RATE>,
+    scheme: CommitmentScheme,
+    vector: &[F],
+) -> F {
+    match scheme {
+        CommitmentScheme::Plain => hash_with_tag(poseidon, None, vector),
+        CommitmentScheme::DomainSeparated => {
+            hash_with_tag(poseidon, Some(CommitmentScheme::LEAF_TAG), vector)
+        }
+    }
+}
+
+/// Hashes the metadata fields of an entry with respect to the commitment `scheme`.
+pub(crate) fn hash_metadata<F: ScalarField, const T: usize, const RATE: usize>(
+    poseidon: &Poseidon<F, F, T, RATE>,
+    scheme: CommitmentScheme,
+    metadata: &[F],
+) -> F {
+    match scheme {
+        CommitmentScheme::Plain => hash_with_tag(poseidon, None, metadata),
+        CommitmentScheme::DomainSeparated => {
+            hash_with_tag(poseidon, Some(CommitmentScheme::METADATA_TAG), metadata)
+        }
+    }
+}
+
+/// Hashes a vector along with its metadata fields to a leaf with respect to the commitment
+/// `scheme`, i.e. the hash of the vector hash & the metadata hash.
+pub(crate) fn hash_record<F: ScalarField, const T: usize, const RATE: usize>(
+    poseidon: &Poseidon<F, F, T, RATE>,
+    scheme: CommitmentScheme,
+    vector: &[F],
+    metadata: &[F],
+) -> F {
+    let inputs = [hash_leaf(poseidon, scheme, vector), hash_metadata(poseidon, scheme, metadata)];
+    match scheme {
+        CommitmentScheme::Plain => hash_with_tag(poseidon, None, &inputs),
+        CommitmentScheme::DomainSeparated => {
+            hash_with_tag(poseidon, Some(CommitmentScheme::RECORD_TAG), &inputs)
+        }
+    }
+}
+
+/// Hashes the children of an internal node with respect to the commitment `scheme`.
+pub(crate) fn hash_node<F: ScalarField, const T: usize, const RATE: usize>(
+    poseidon: &Poseidon<F, F, T, RATE>,
+    scheme: CommitmentScheme,
+    children: &[F],
+) -> F {
+    match scheme {
+        CommitmentScheme::Plain => hash_with_tag(poseidon, None, children),
+        CommitmentScheme::DomainSeparated => {
+            hash_with_tag(poseidon, Some(CommitmentScheme::NODE_TAG), children)
+        }
+    }
+}
+
+/// Returns the leaf that is used for padding with respect to the commitment `scheme`.
+pub(crate) fn padding_leaf<F: ScalarField, const T: usize, const RATE: usize>(
+    poseidon: &Poseidon<F, F, T, RATE>,
+    scheme: CommitmentScheme,
+) -> F {
+    match scheme {
+        CommitmentScheme::Plain => F::zero(),
+        CommitmentScheme::DomainSeparated => {
+            hash_with_tag(poseidon, Some(CommitmentScheme::PADDING_TAG), &[])
+        }
+    }
+}
+
+/// Returns the leaf of a deleted vector, which is the same for all schemes.
+pub(crate) fn tombstone_leaf<F: ScalarField, const T: usize, const RATE: usize>(
+    poseidon: &Poseidon<F, F, T, RATE>,
+) -> F {
+    hash_with_tag(poseidon, Some(CommitmentScheme::TOMBSTONE_TAG), &[])
+}
+
+/// Binds the number of vectors `len` and their dimension `dim` to the `tree_root` with
+/// respect to the commitment `scheme`, returning the final root.
+pub(crate) fn bind_root<F: ScalarField, const T: usize, const RATE: usize>(
+    poseidon: &Poseidon<F, F, T, RATE>,
+    scheme: CommitmentScheme,
+    tree_root: F,
+    len: usize,
+    dim: usize,
+) -> F {
+    match scheme {
+        CommitmentScheme::Plain => tree_root,
+        CommitmentScheme::DomainSeparated => hash_with_tag(
+            poseidon,
+            Some(CommitmentScheme::ROOT_TAG),
+            &[tree_root, F::from(len as u64), F::from(dim as u64)],
+        ),
+    }
+}
+
+/// A Merkle tree with Poseidon hashes over a set of (quantized) vectors, computed exactly
+/// as [`crate::gadget::vectordb::VectorDBInstructions::merkle_commitment_with_scheme`] does:
+///
+/// - each vector is hashed to a single field element, which is the leaf
+/// - leaves are padded until there are a power of `RATE` many
+/// - each internal node is the hash of its `RATE` children
+/// - the root binds the number of vectors & their dimension, if the scheme is domain-separated
+#[derive(Clone, Debug)]
+pub struct MerkleTree<F: ScalarField, const T: usize, const RATE: usize> {
+    /// Nodes at each level, starting from the (padded) leaves up to the tree root.
+    layers: Vec<Vec<F>>,
+    params: CommitmentParams<T, RATE>,
+    len: usize,
+    dim: usize,
+}
+
+impl<F: ScalarField, const T: usize, const RATE: usize> MerkleTree<F, T, RATE> {
+    /// Builds the tree over the given quantized `vectors` with the commitment `params`.
+    pub fn new(vectors: &[Vec<F>], params: &CommitmentParams<T, RATE>) -> Self {
+        Self::new_with_capacity(vectors, vectors.len(), params)
+    }
+
+    /// Same as `new`, but pads the leaves until there are at least `capacity` many
+    /// (rounded up to a power of `RATE`), so that there are empty slots to insert vectors into.
+    pub fn new_with_capacity(
+        vectors: &[Vec<F>],
+        capacity: usize,
+        params: &CommitmentParams<T, RATE>,
+    ) -> Self {
+        assert!(!vectors.is_empty(), "expected at least one vector");
+        let poseidon = poseidon(params);
+
+        // hash each vector to a field element
+        let leaves: Vec<F> =
+            vectors.iter().map(|v| hash_leaf(&poseidon, params.scheme, v.as_slice())).collect();
+        Self::from_leaves(leaves, capacity, params, vectors[0].len())
+    }
+
+    /// Same as `new`, but each leaf also binds the `metadata` fields of its vector, computed
+    /// exactly as `merkle_commitment_with_metadata` does.
+    pub fn new_with_metadata(
+        vectors: &[Vec<F>],
+        metadata: &[Vec<F>],
+        params: &CommitmentParams<T, RATE>,
+    ) -> Self {
+        assert!(!vectors.is_empty(), "expected at least one vector");
+        assert_eq!(vectors.len(), metadata.len(), "expected metadata for each vector");
+        let poseidon = poseidon(params);
+
+        let leaves: Vec<F> = vectors
+            .iter()
+            .zip(metadata.iter())
+            .map(|(v, m)| hash_record(&poseidon, params.scheme, v.as_slice(), m.as_slice()))
+            .collect();
+        Self::from_leaves(leaves, vectors.len(), params, vectors[0].len())
+    }
+
+    /// Builds the tree over the given `leaves` of vectors with dimension `dim`, padding them
+    /// until there are at least `capacity` many (rounded up to a power of `RATE`).
+    fn from_leaves(
+        mut leaves: Vec<F>,
+        capacity: usize,
+        params: &CommitmentParams<T, RATE>,
+        dim: usize,
+    ) -> Self {
+        let len = leaves.len();
+        assert!(len <= capacity, "expected at most `capacity` many vectors");
+        assert!(RATE > 1, "expected at least two children for each node");
+        let poseidon = poseidon(params);
+        let scheme = params.scheme;
+
+        let mut num_leaves = 1;
+        while num_leaves < capacity {
+            num_leaves *= RATE;
+        }
+        leaves.resize(num_leaves, padding_leaf(&poseidon, scheme));
+
+        let mut layers = vec![leaves];
+        while layers.last().unwrap().len() > 1 {
+            let next = layers
+                .last()
+                .unwrap()
+                .chunks(RATE)
+                .map(|children| hash_node(&poseidon, scheme, children))
+                .collect();
+            layers.push(next);
+        }
+
+        Self { layers, params: *params, len, dim }
+    }
+
+    /// Root of the tree, without the number of vectors & their dimension bound to it.
+    pub fn tree_root(&self) -> F {
+        self.layers.last().unwrap()[0]
+    }
+
+    /// Merkle root of the tree, which is the same as `merkle_commitment_with_params`.
+    pub fn root(&self) -> F {
+        bind_root(&poseidon(&self.params), self.params.scheme, self.tree_root(), self.len, self.dim)
+    }
+
+    /// Number of vectors in the tree, excluding padding.
+    pub fn num_vectors(&self) -> usize {
+        self.len
+    }
+
+    /// Number of levels above the leaves, i.e. the length of each authentication path.
+    pub fn depth(&self) -> usize {
+        self.layers.len() - 1
+    }
+
+    /// Leaf at the given `index`, i.e. the hash of the vector at that index.
+    pub fn leaf(&self, index: usize) -> F {
+        self.layers[0][index]
+    }
+
+    /// Authentication path of the leaf at the given `index`.
+    pub fn path(&self, index: usize) -> MerklePath<F> {
+        assert!(index < self.layers[0].len(), "index out of bounds");
+
+        let mut siblings = Vec::with_capacity(self.depth() * (RATE - 1));
+        let mut index_in_layer = index;
+        for layer in self.layers[..self.depth()].iter() {
+            let first = index_in_layer - index_in_layer % RATE;
+            siblings
+                .extend((first..first + RATE).filter(|i| *i != index_in_layer).map(|i| layer[i]));
+            index_in_layer /= RATE;
+        }
+
+        MerklePath { index, siblings }
+    }
+
+    /// Inserts a vector into the first empty slot, same as [`MerkleUpdate::Insert`] does,
+    /// and returns its index.
+    ///
+    /// [`MerkleUpdate::Insert`]: crate::gadget::vectordb::MerkleUpdate::Insert
+    pub fn insert(&mut self, vector: &[F]) -> usize {
+        assert!(self.len < self.layers[0].len(), "tree is full");
+        assert_eq!(vector.len(), self.dim, "expected vector of same dimension");
+
+        let index = self.len;
+        let poseidon = poseidon(&self.params);
+        self.set_leaf(&poseidon, index, hash_leaf(&poseidon, self.params.scheme, vector));
+        self.len += 1;
+
+        index
+    }
+
+    /// Replaces the vector at the given `index`, same as [`MerkleUpdate::Replace`] does.
+    ///
+    /// [`MerkleUpdate::Replace`]: crate::gadget::vectordb::MerkleUpdate::Replace
+    pub fn replace(&mut self, index: usize, vector: &[F]) {
+        assert!(index < self.len, "index out of bounds");
+        assert_eq!(vector.len(), self.dim, "expected vector of same dimension");
+
+        let poseidon = poseidon(&self.params);
+        self.set_leaf(&poseidon, index, hash_leaf(&poseidon, self.params.scheme, vector));
+    }
+
+    /// Deletes the vector at the given `index` by replacing it with a tombstone, same as
+    /// [`MerkleUpdate::Delete`] does.
+    ///
+    /// [`MerkleUpdate::Delete`]: crate::gadget::vectordb::MerkleUpdate::Delete
+    pub fn delete(&mut self, index: usize) {
+        assert!(index < self.len, "index out of bounds");
+
+        let poseidon = poseidon(&self.params);
+        self.set_leaf(&poseidon, index, tombstone_leaf(&poseidon));
+    }
+
+    /// Sets the leaf at the given `index`, and re-computes the nodes on its path.
+    fn set_leaf(&mut self, poseidon: &Poseidon<F, F, T, RATE>, index: usize, leaf: F) {
+        self.layers[0][index] = leaf;
+
+        let mut index = index;
+        for level in 1..self.layers.len() {
+            index /= RATE;
+            let children = &self.layers[level - 1][index * RATE..(index + 1) * RATE];
+            let node = hash_node(poseidon, self.params.scheme, children);
+            self.layers[level][index] = node;
+        }
+    }
+}
@@ -0,0 +1,6 @@
+//! Native (i.e. out-of-circuit) counterparts of the gadgets, which are useful for a server
+//! that needs to compute commitments & openings without building a circuit.
+pub mod incremental;
+pub mod merkle;
+pub mod query;
+pub mod sparse;
@@ -0,0 +1,36 @@
+use halo2_base::utils::ScalarField;
+
+use super::merkle::{hash_with_tag, poseidon};
+use crate::gadget::vectordb::{CommitmentParams, CommitmentScheme};
+
+/// Hashes `inputs` followed by the `salt` with the domain `tag`, which is prepended regardless
+/// of the scheme of `params`.
+fn hash_salted<F: ScalarField, const T: usize, const RATE: usize>(
+    params: &CommitmentParams<T, RATE>,
+    tag: u64,
+    inputs: &[F],
+    salt: F,
+) -> F {
+    let inputs: Vec<F> = inputs.iter().copied().chain([salt]).collect();
+    hash_with_tag(&poseidon(params), Some(tag), &inputs)
+}
+
+/// Commits to the quantized `query` with a blinding `salt`, same as `commit_query` (and the
+/// query commitment exposed by `threshold_match`).
+pub fn commit_query<F: ScalarField, const T: usize, const RATE: usize>(
+    query: &[F],
+    salt: F,
+    params: &CommitmentParams<T, RATE>,
+) -> F {
+    hash_salted(params, CommitmentScheme::QUERY_TAG, query, salt)
+}
+
+/// Hashes the quantized `result` with the `salt` of its query, same as `public_result` with
+/// [`crate::gadget::vectordb::ResultVisibility::Hashed`].
+pub fn hash_result<F: ScalarField, const T: usize, const RATE: usize>(
+    result: &[F],
+    salt: F,
+    params: &CommitmentParams<T, RATE>,
+) -> F {
+    hash_salted(params, CommitmentScheme::RESULT_TAG, result, salt)
+}
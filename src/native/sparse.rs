@@ -0,0 +1,152 @@
+use halo2_base::utils::ScalarField;
+use snark_verifier_sdk::snark_verifier::util::hash::Poseidon;
+use std::collections::HashMap;
+
+use super::merkle::{
+    hash_leaf, hash_metadata, hash_node, hash_with_tag, padding_leaf, poseidon, MerklePath,
+};
+use crate::gadget::vectordb::{CommitmentParams, CommitmentScheme};
+
+/// Hashes an entry, i.e. its id along with the hashes of its vector & metadata, to a leaf
+/// with respect to the commitment `scheme`.
+pub(crate) fn hash_entry<F: ScalarField, const T: usize, const RATE: usize>(
+    poseidon: &Poseidon<F, F, T, RATE>,
+    scheme: CommitmentScheme,
+    id: u64,
+    vector: &[F],
+    metadata: &[F],
+) -> F {
+    let inputs = [
+        F::from(id),
+        hash_leaf(poseidon, scheme, vector),
+        hash_metadata(poseidon, scheme, metadata),
+    ];
+    match scheme {
+        CommitmentScheme::Plain => hash_with_tag(poseidon, None, &inputs),
+        CommitmentScheme::DomainSeparated => {
+            hash_with_tag(poseidon, Some(CommitmentScheme::ENTRY_TAG), &inputs)
+        }
+    }
+}
+
+/// A sparse Merkle tree with Poseidon hashes, where the entry with id `id` is at the leaf with
+/// index `id`, so that the position of an entry never changes. Each node has `RATE` children
+/// (which must be a power of two), empty leaves are padding leaves, and only the nodes that are
+/// not the root of an empty subtree are stored.
+///
+/// See [`crate::gadget::vectordb::VectorDBInstructions::verify_sparse_inclusion`] and
+/// [`crate::gadget::vectordb::VectorDBInstructions::verify_sparse_non_inclusion`] for the
+/// circuit counterparts.
+#[derive(Clone, Debug)]
+pub struct SparseMerkleTree<F: ScalarField, const T: usize, const RATE: usize> {
+    /// Non-empty nodes, keyed by their level (leaves are at level 0) & index within the level.
+    nodes: HashMap<(usize, u64), F>,
+    /// Root of an empty subtree at each level, starting with the padding leaf.
+    zeros: Vec<F>,
+    params: CommitmentParams<T, RATE>,
+}
+
+impl<F: ScalarField, const T: usize, const RATE: usize> SparseMerkleTree<F, T, RATE> {
+    /// Creates an empty tree for ids in `[0, RATE^depth)`, with the commitment `params`.
+    pub fn new(depth: usize, params: &CommitmentParams<T, RATE>) -> Self {
+        assert!(RATE.is_power_of_two() && RATE > 1, "expected arity to be a power of two");
+        assert!(depth * RATE.trailing_zeros() as usize <= 64, "ids are at most 64 bits");
+        let poseidon = poseidon(params);
+        let scheme = params.scheme;
+
+        let mut zeros = vec![padding_leaf(&poseidon, scheme)];
+        for level in 0..depth {
+            let zero = zeros[level];
+            zeros.push(hash_node(&poseidon, scheme, &[zero; RATE]));
+        }
+
+        Self { nodes: HashMap::new(), zeros, params: *params }
+    }
+
+    /// Inserts (or overwrites) the entry with the given `id`, holding a (quantized) `vector`
+    /// along with its `metadata` fields.
+    pub fn insert(&mut self, id: u64, vector: &[F], metadata: &[F]) {
+        let poseidon = poseidon(&self.params);
+        let leaf = hash_entry(&poseidon, self.params.scheme, id, vector, metadata);
+        self.set_leaf(&poseidon, id, leaf);
+    }
+
+    /// Removes the entry with the given `id`, making its leaf empty again.
+    pub fn remove(&mut self, id: u64) {
+        let poseidon = poseidon(&self.params);
+        self.set_leaf(&poseidon, id, self.zeros[0]);
+    }
+
+    /// Merkle root of the tree.
+    pub fn root(&self) -> F {
+        self.node(self.depth(), 0)
+    }
+
+    /// Number of levels above the leaves, i.e. the number of digits of an id in base `RATE`.
+    pub fn depth(&self) -> usize {
+        self.zeros.len() - 1
+    }
+
+    /// Whether the given `id` is within the tree, i.e. less than `RATE^depth`.
+    fn in_bounds(&self, id: u64) -> bool {
+        let bits = self.depth() * RATE.trailing_zeros() as usize;
+        bits == 64 || id < 1 << bits
+    }
+
+    /// Leaf of the given `id`, which is the padding leaf if there is no such entry.
+    pub fn leaf(&self, id: u64) -> F {
+        self.node(0, id)
+    }
+
+    /// Whether there is an entry with the given `id`.
+    pub fn contains(&self, id: u64) -> bool {
+        self.nodes.contains_key(&(0, id))
+    }
+
+    /// Authentication path of the leaf of the given `id`, which is used both for inclusion
+    /// and non-inclusion proofs.
+    pub fn path(&self, id: u64) -> MerklePath<F> {
+        assert!(self.in_bounds(id), "id out of bounds");
+
+        let rate = RATE as u64;
+        let mut siblings = Vec::with_capacity(self.depth() * (RATE - 1));
+        let mut index = id;
+        for level in 0..self.depth() {
+            let first = index - index % rate;
+            siblings
+                .extend((first..first + rate).filter(|i| *i != index).map(|i| self.node(level, i)));
+            index /= rate;
+        }
+
+        MerklePath { index: id as usize, siblings }
+    }
+
+    /// Node at the given `level` & `index`, which is the root of an empty subtree if not stored.
+    fn node(&self, level: usize, index: u64) -> F {
+        self.nodes.get(&(level, index)).copied().unwrap_or(self.zeros[level])
+    }
+
+    /// Sets the leaf of the given `id`, and re-computes the nodes on its path.
+    fn set_leaf(&mut self, poseidon: &Poseidon<F, F, T, RATE>, id: u64, leaf: F) {
+        assert!(self.in_bounds(id), "id out of bounds");
+
+        let rate = RATE as u64;
+        let mut index = id;
+        let mut node = leaf;
+        for level in 0..=self.depth() {
+            // only keep the nodes that are not empty
+            if node == self.zeros[level] {
+                self.nodes.remove(&(level, index));
+            } else {
+                self.nodes.insert((level, index), node);
+            }
+
+            if level < self.depth() {
+                let first = index - index % rate;
+                let children: Vec<F> = (first..first + rate).map(|i| self.node(level, i)).collect();
+                node = hash_node(poseidon, self.params.scheme, &children);
+                index /= rate;
+            }
+        }
+    }
+}
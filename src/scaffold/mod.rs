@@ -130,6 +130,24 @@ mod eth {
         _f1: PhantomData<F1>,
     }
 
+    impl<T, FN, F1> EthScaffold<T, FN, F1>
+    where
+        FN: FnOnce(
+            &mut GateThreadBuilder<Fr>,
+            &EthChip<Fr>,
+            &mut KeccakChip<Fr>,
+            T,
+            &mut Vec<AssignedValue<Fr>>,
+        ) -> F1,
+        F1: FnOnce(&mut Context<Fr>, &mut Context<Fr>, &EthChip<Fr>) + Clone,
+    {
+        /// Creates the same circuit as `run_eth_builder` from the given function and inputs,
+        /// without reading them from the command line (e.g. to run the mock prover in tests).
+        pub fn new(f: FN, private_inputs: T) -> Self {
+            Self { f, private_inputs, _f1: PhantomData }
+        }
+    }
+
     impl<T, FN, F1> PreCircuit for EthScaffold<T, FN, F1>
     where
         FN: FnOnce(
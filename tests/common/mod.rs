@@ -1,9 +1,16 @@
 #![allow(dead_code)]
 
+use axiom_eth::util::circuit::PreCircuit;
 use byteorder::{LittleEndian, ReadBytesExt};
-use halo2_base::utils::ScalarField;
+use halo2_base::gates::builder::{
+    CircuitBuilderStage, GateThreadBuilder, RangeCircuitBuilder, RangeWithInstanceCircuitBuilder,
+};
+use halo2_base::halo2_proofs::dev::MockProver;
+use halo2_base::utils::{fs::gen_srs, ScalarField};
 use halo2_base::AssignedValue;
+use snark_verifier_sdk::CircuitExt;
 use std::cmp::Ordering;
+use std::env::set_var;
 use std::fs::read;
 use std::io::Cursor;
 
@@ -30,6 +37,52 @@ pub fn compare_fields<F: ScalarField>(p: &F, q: &F) -> bool {
     p.cmp(q) == Ordering::Equal
 }
 
+/// Runs the mock prover with the constraints within `builder` at degree `k`, and
+/// returns whether they are satisfied.
+///
+/// The range lookup table has `2^lookup_bits` rows, so `k` must be larger than `lookup_bits`.
+pub fn is_satisfied<F: ScalarField>(
+    mut builder: GateThreadBuilder<F>,
+    k: u32,
+    lookup_bits: usize,
+) -> bool {
+    assert!(k as usize > lookup_bits, "expected k to be larger than the lookup bits");
+    set_var("LOOKUP_BITS", lookup_bits.to_string());
+    builder.config(k as usize, Some(9));
+    let circuit = RangeCircuitBuilder::mock(builder);
+    MockProver::run(k, &circuit, vec![]).unwrap().verify().is_ok()
+}
+
+/// Same as `is_satisfied`, but makes `assigned_instances` public, and checks them against the
+/// `instances` the verifier expects.
+pub fn is_satisfied_with_instances<F: ScalarField>(
+    mut builder: GateThreadBuilder<F>,
+    k: u32,
+    lookup_bits: usize,
+    assigned_instances: Vec<AssignedValue<F>>,
+    instances: Vec<F>,
+) -> bool {
+    assert!(k as usize > lookup_bits, "expected k to be larger than the lookup bits");
+    set_var("LOOKUP_BITS", lookup_bits.to_string());
+    builder.config(k as usize, Some(9));
+    let circuit = RangeWithInstanceCircuitBuilder::new(
+        RangeCircuitBuilder::mock(builder),
+        assigned_instances,
+    );
+    MockProver::run(k, &circuit, vec![instances]).unwrap().verify().is_ok()
+}
+
+/// Runs the mock prover with the circuit created from `precircuit` at degree `k`, and
+/// returns whether its constraints are satisfied.
+///
+/// This is for circuits other than a `RangeCircuitBuilder`, e.g. an `EthScaffold` which is
+/// what `run_eth` builds.
+pub fn is_precircuit_satisfied<P: PreCircuit>(precircuit: P, k: u32) -> bool {
+    let params = gen_srs(k);
+    let circuit = precircuit.create_circuit(CircuitBuilderStage::Mock, None, &params);
+    MockProver::run(k, &circuit, circuit.instances()).unwrap().verify().is_ok()
+}
+
 /// Generate a random vector with `dim` elements.
 pub fn random_vector(dim: usize) -> Vec<f64> {
     let mut vector: Vec<f64> = Vec::with_capacity(dim);
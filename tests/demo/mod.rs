@@ -2,6 +2,7 @@
 
 use crate::{common, distances, vectordb};
 use halo2_base::halo2_proofs::halo2curves::bn256::Fr as F;
+use halo2_scaffold::gadget::vectordb::CommitmentScheme;
 
 pub struct DemoDB<const K: usize, const I: usize> {
     database: Vec<Vec<f64>>,
@@ -33,6 +34,19 @@ impl<const K: usize, const I: usize> DemoDB<K, I> {
 
         result
     }
+
+    /// Same as `ann`, but searches within the clusters of the `P` nearest centroids.
+    pub fn ann_multi_probe<const P: usize>(&self, vector: Vec<f64>) -> Vec<f64> {
+        let probes = vectordb::top_k(&vector, &self.centroids, P, &distances::euclidean_distance);
+        let union: Vec<Vec<f64>> = probes
+            .iter()
+            .flat_map(|id| common::select_cluster(&self.database, &self.cluster_ids, *id))
+            .collect();
+
+        let (_, result) = vectordb::nearest_vector(&vector, &union, &distances::euclidean_distance);
+
+        result
+    }
 }
 
 pub struct DemoZKDB<const K: usize, const I: usize> {
@@ -40,51 +54,56 @@ pub struct DemoZKDB<const K: usize, const I: usize> {
     cluster_ids: Vec<usize>,
     centroids: Vec<Vec<f64>>,
     cluster_roots: Vec<F>, // one merkle root for each cluster
-    centroids_root: F,     // merkle root over the centroids
+    ivf_root: F,           // merkle root over the centroids, binding the cluster roots
     pub database_root: F,  // merkle root over the database
 }
 
 impl<'a, const K: usize, const I: usize> DemoZKDB<K, I> {
     pub fn new(database: Vec<Vec<f64>>) -> Self {
-        let (centroids, cluster_ids) = vectordb::chip_kmeans::<K, I>(&database);
+        // K-means and the roots over the database & each cluster are computed within the same
+        // index circuit, which proves that the clusters partition the database; it is only
+        // witnessed here as the mock prover is too costly (see `vectordb_test` for that)
+        let (_, centroids, cluster_ids, database_root, _, cluster_roots) =
+            vectordb::index::<K, I>(&database, CommitmentScheme::DomainSeparated);
         let centroids = centroids.to_vec();
 
-        // FIXME: these should be done in the same circuit
-        // however with the current costs as is, it would make things even worse
-        let database_root: F = vectordb::chip_merkle(&database);
-        let centroids_root: F = vectordb::chip_merkle(&centroids);
-        let cluster_roots: Vec<F> = (0..centroids.len())
-            .map(|cluster_id| {
-                let cluster = common::select_cluster(&database, &cluster_ids, cluster_id);
-                vectordb::chip_merkle(&cluster)
-            })
-            .collect();
-
         assert_eq!(cluster_ids.len(), database.len());
         assert_eq!(cluster_roots.len(), centroids.len());
+        let ivf_root = vectordb::native_ivf_root(
+            &centroids,
+            &cluster_roots,
+            CommitmentScheme::DomainSeparated,
+        );
 
-        Self { database, cluster_ids, centroids, database_root, centroids_root, cluster_roots }
+        Self { database, cluster_ids, centroids, database_root, ivf_root, cluster_roots }
     }
 
     /// Querying (i.e. inference) takes in a query vector, and returns the most similar vector within the database.
     pub fn ann(&self, vector: Vec<f64>) -> Vec<f64> {
-        // find nearest centroid & its cluster id
-        let (cluster_id, _, centroids_root) =
-            vectordb::chip_nearest_vector(&vector, &self.centroids);
-        assert!(
-            common::compare_fields(&self.centroids_root, &centroids_root),
-            "centroid roots do not match"
-        );
-
-        // get vectors within the cluster
-        let cluster = common::select_cluster(&self.database, &self.cluster_ids, cluster_id);
+        self.ann_multi_probe::<1>(vector)
+    }
 
-        // choose nearest vector
-        let (_, result, cluster_root) = vectordb::chip_nearest_vector(&vector, &cluster);
-        assert!(
-            common::compare_fields(&self.cluster_roots[cluster_id], &cluster_root),
-            "cluster roots do not match"
+    /// Same as `ann`, but searches within the clusters of the `P` nearest centroids, which
+    /// gives a better recall for queries near the cluster boundaries.
+    pub fn ann_multi_probe<const P: usize>(&self, vector: Vec<f64>) -> Vec<f64> {
+        // the prover finds the nearest centroids & opens their clusters
+        let probes = vectordb::top_k(&vector, &self.centroids, P, &distances::euclidean_distance);
+        let clusters: [Vec<Vec<f64>>; P] = core::array::from_fn(|i| {
+            common::select_cluster(&self.database, &self.cluster_ids, probes[i])
+        });
+
+        // the probes & the search within their union are proven within a single circuit, where
+        // the verifier sets the IVF root of the index as its public input
+        let (_, _, result, satisfied) = vectordb::chip_ivf_query_multi_probe_with_root::<P>(
+            &vector,
+            &self.centroids,
+            &self.cluster_roots,
+            &clusters,
+            CommitmentScheme::DomainSeparated,
+            "euclidean",
+            self.ivf_root,
         );
+        assert!(satisfied, "query is not proven against the IVF root");
 
         result
     }
@@ -32,6 +32,29 @@ mod test {
         common::assert_vectors_eq(&result_chip, &result_native);
     }
 
+    #[test]
+    fn test_random_multi_probe() {
+        const DIM: usize = 4;
+        const NUM_VECS: usize = 12;
+        const K: usize = 4;
+        const I: usize = 4;
+        const P: usize = 2;
+
+        let vectors = common::random_vectors(DIM, NUM_VECS);
+        let query = common::random_vector(DIM);
+
+        // native results
+        let db_native = demo::DemoDB::<K, I>::new(vectors.clone());
+        let result_native = db_native.ann_multi_probe::<P>(query.clone());
+
+        // chip results
+        let db_chip = demo::DemoZKDB::<K, I>::new(vectors);
+        let result_chip = db_chip.ann_multi_probe::<P>(query.clone());
+
+        // compare
+        common::assert_vectors_eq(&result_chip, &result_native);
+    }
+
     // this one might take some time
     #[test]
     fn test_random_large() {
@@ -6,18 +6,29 @@ const PRECISION_BITS: u32 = 48;
 // poseidon params
 const T: usize = 3;
 const RATE: usize = 2;
-const R_F: usize = 8;
-const R_P: usize = 57;
+const PARAMS: CommitmentParams<T, RATE> = CommitmentParams::DEFAULT;
 
+use axiom_eth::{keccak::KeccakChip, EthChip};
+use ethers_core::utils::keccak256;
 use halo2_base::halo2_proofs::halo2curves::bn256::Fr as F;
-use halo2_base::{gates::builder::GateThreadBuilder, AssignedValue};
+use halo2_base::{gates::builder::GateThreadBuilder, utils::ScalarField, AssignedValue, Context};
 use halo2_scaffold::gadget::distance::{DistanceChip, DistanceInstructions};
 use halo2_scaffold::gadget::{
     fixed_point::FixedPointChip,
     fixed_point_vec::FixedPointVectorInstructions,
-    vectordb::{VectorDBChip, VectorDBInstructions},
+    vectordb::{
+        CommitmentParams, CommitmentScheme, Comparison, Condition, MerkleUpdate, ResultVisibility,
+        VectorDBChip, VectorDBInstructions,
+    },
 };
-use poseidon::PoseidonChip;
+use halo2_scaffold::native::{
+    incremental::IncrementalMerkleTree,
+    merkle::{self, MerkleTree},
+    query::{commit_query, hash_result},
+    sparse::SparseMerkleTree,
+};
+use halo2_scaffold::scaffold::EthScaffold;
+use std::env::set_var;
 
 use crate::common;
 
@@ -33,22 +44,42 @@ pub fn kmeans<const K: usize, const I: usize>(
     distance: &dyn Fn(&Vec<f64>, &Vec<f64>) -> f64,
 ) -> ([Vec<f64>; K], Vec<usize>) {
     assert!(K < vectors.len(), "you must provide at least K many vectors");
-    // dimensions of each vector
-    let n = vectors[0].len();
 
     // take first K vectors as the initial centroids
     let mut centroids: [Vec<f64>; K] =
         vectors.iter().take(K).cloned().collect::<Vec<Vec<f64>>>().try_into().unwrap();
 
-    // number of vectors within each cluster
-    let mut cluster_sizes: [usize; K] = [0; K];
-
     // cluster id of each vector
     let mut cluster_ids: Vec<usize> = (0..vectors.len()).map(|_| 0).collect();
 
     for _iter in 0..I {
-        // assign each vector to closest centroid
-        vectors.iter().enumerate().for_each(|(i, v)| {
+        (centroids, cluster_ids) = kmeans_iteration(vectors, &centroids, distance);
+
+        // println!("{:?}:\t{:?}\n\t{:?}", _iter, centroids, cluster_ids);
+    }
+
+    (centroids, cluster_ids)
+}
+
+/// A single iteration of k-means, that assigns each vector to the closest centroid
+/// and then updates the centroids. An empty cluster keeps its previous centroid.
+///
+/// Returns the updated centroids and the cluster ids of each vector.
+pub fn kmeans_iteration<const K: usize>(
+    vectors: &Vec<Vec<f64>>,
+    centroids: &[Vec<f64>; K],
+    distance: &dyn Fn(&Vec<f64>, &Vec<f64>) -> f64,
+) -> ([Vec<f64>; K], Vec<usize>) {
+    // dimensions of each vector
+    let n = centroids[0].len();
+
+    // number of vectors within each cluster
+    let mut cluster_sizes: [usize; K] = [0; K];
+
+    // assign each vector to closest centroid
+    let cluster_ids: Vec<usize> = vectors
+        .iter()
+        .map(|v| {
             // compute distances to every centroid
             let distances: Vec<f64> = centroids.iter().map(|c| distance(v, c)).collect();
 
@@ -58,36 +89,226 @@ pub fn kmeans<const K: usize, const I: usize>(
             // return the corresponding index as the cluster id
             let id: usize = distances.into_iter().enumerate().find(|(_, d)| *d == min).unwrap().0;
 
-            cluster_ids[i] = id;
             cluster_sizes[id] += 1;
+            id
+        })
+        .collect();
+
+    // update centroids
+    let mut centroids: [Vec<f64>; K] = centroids.clone();
+    for id in 0..K {
+        if cluster_sizes[id] == 0 {
+            continue;
+        }
+
+        // mean of vectors in this cluster
+        let mut mean: Vec<f64> = (0..n).map(|_| 0.0).collect();
+        vectors.iter().enumerate().for_each(|(v_i, v)| {
+            if cluster_ids[v_i] == id {
+                for i in 0..n {
+                    mean[i] += v[i];
+                }
+            }
         });
+        for i in 0..n {
+            mean[i] /= cluster_sizes[id] as f64;
+        }
+
+        // assign to centroid
+        centroids[id] = mean;
+    }
+
+    (centroids, cluster_ids)
+}
+
+/// Spherical k-means, which is the same as `kmeans` except that the centroids are normalized
+/// to unit norm after each update.
+pub fn spherical_kmeans<const K: usize, const I: usize>(
+    vectors: &Vec<Vec<f64>>,
+    distance: &dyn Fn(&Vec<f64>, &Vec<f64>) -> f64,
+) -> ([Vec<f64>; K], Vec<usize>) {
+    assert!(K < vectors.len(), "you must provide at least K many vectors");
+
+    // take first K vectors as the initial centroids
+    let mut centroids: [Vec<f64>; K] =
+        vectors.iter().take(K).cloned().collect::<Vec<Vec<f64>>>().try_into().unwrap();
+
+    // cluster id of each vector
+    let mut cluster_ids: Vec<usize> = (0..vectors.len()).map(|_| 0).collect();
+
+    for _iter in 0..I {
+        (centroids, cluster_ids) = kmeans_iteration(vectors, &centroids, distance);
+        centroids = centroids.map(|c| normalize(&c));
+    }
+
+    (centroids, cluster_ids)
+}
+
+/// Scales a vector to unit norm.
+pub fn normalize(v: &Vec<f64>) -> Vec<f64> {
+    let norm = v.iter().map(|v_i| v_i * v_i).sum::<f64>().sqrt();
+    v.iter().map(|v_i| v_i / norm).collect()
+}
+
+/// A straightforward k-medoids algorithm, where medoids are members of the given vectors.
+///
+/// Each iteration assigns vectors to their closest medoid, and then picks the member with
+/// the least total distance to other members as the new medoid, where an empty cluster keeps
+/// its previous medoid. Takes the first `K` vectors as the initial medoids, and returns the
+/// medoid indices along with the cluster ids of each vector in the given order.
+pub fn kmedoids<const K: usize, const I: usize>(
+    vectors: &Vec<Vec<f64>>,
+    distance: &dyn Fn(&Vec<f64>, &Vec<f64>) -> f64,
+) -> ([usize; K], Vec<usize>) {
+    assert!(K < vectors.len(), "you must provide at least K many vectors");
+    let n = vectors.len();
+
+    // pairwise distances, same as the circuit we assume a symmetric distance
+    // where each vector has zero distance to itself
+    let mut distances: Vec<Vec<f64>> = vec![vec![0.0; n]; n];
+    for i in 0..n {
+        for j in (i + 1)..n {
+            distances[i][j] = distance(&vectors[i], &vectors[j]);
+            distances[j][i] = distances[i][j];
+        }
+    }
+
+    // take first K vectors as the initial medoids
+    let mut medoids: [usize; K] = core::array::from_fn(|k| k);
 
-        // update centroids
+    // cluster id of each vector
+    let mut cluster_ids: Vec<usize> = (0..n).map(|_| 0).collect();
+
+    for _iter in 0..I {
+        // assign each vector to closest medoid
+        cluster_ids = (0..n)
+            .map(|i| {
+                let medoid_distances: Vec<f64> = medoids.iter().map(|m| distances[i][*m]).collect();
+                let min: f64 = medoid_distances.iter().fold(f64::INFINITY, |a, &b| a.min(b));
+                medoid_distances.into_iter().position(|d| d == min).unwrap()
+            })
+            .collect();
+
+        // update medoids, an empty cluster keeps its previous medoid
         for id in 0..K {
-            // mean of vectors in this cluster
-            let mut mean: Vec<f64> = (0..n).map(|_| 0.0).collect();
-            vectors.iter().enumerate().for_each(|(v_i, v)| {
-                if cluster_ids[v_i] == id {
-                    for i in 0..n {
-                        mean[i] += v[i];
+            if !cluster_ids.contains(&id) {
+                continue;
+            }
+            let costs: Vec<f64> = (0..n)
+                .map(|i| {
+                    if cluster_ids[i] == id {
+                        (0..n).filter(|j| cluster_ids[*j] == id).map(|j| distances[i][j]).sum()
+                    } else {
+                        f64::INFINITY
                     }
+                })
+                .collect();
+            let min: f64 = costs.iter().fold(f64::INFINITY, |a, &b| a.min(b));
+            medoids[id] = costs.into_iter().position(|c| c == min).unwrap();
+        }
+    }
+
+    (medoids, cluster_ids)
+}
+
+/// Hierarchical k-means, that builds a tree of centroids with the given `depth` and
+/// branching factor `B`. Each cluster is clustered again with k-means over its members,
+/// taking its first `B` members as the initial centroids (repeating them if there are not
+/// enough members, or taking the first `B` vectors if there are none).
+///
+/// Returns the centroids at each level, and the leaf cluster ids of each vector.
+pub fn hierarchical_kmeans<const B: usize, const I: usize>(
+    vectors: &Vec<Vec<f64>>,
+    depth: usize,
+    distance: &dyn Fn(&Vec<f64>, &Vec<f64>) -> f64,
+) -> (Vec<Vec<Vec<f64>>>, Vec<usize>) {
+    // members of each cluster, starting with a single cluster of all vectors
+    let mut clusters: Vec<Vec<usize>> = vec![(0..vectors.len()).collect()];
+
+    let mut levels: Vec<Vec<Vec<f64>>> = vec![];
+    for _level in 0..depth {
+        let mut centroids: Vec<Vec<f64>> = vec![];
+        let mut child_clusters: Vec<Vec<usize>> = vec![];
+
+        for members in clusters.iter() {
+            let member_vectors: Vec<Vec<f64>> =
+                members.iter().map(|i| vectors[*i].clone()).collect();
+
+            // take first B members as the initial centroids, repeating them if needed
+            let mut cluster_centroids: [Vec<f64>; B] = core::array::from_fn(|b| {
+                if member_vectors.is_empty() {
+                    vectors[b].clone()
+                } else {
+                    member_vectors[b % member_vectors.len()].clone()
                 }
             });
-            for i in 0..n {
-                mean[i] /= cluster_sizes[id] as f64;
+            let mut member_ids: Vec<usize> = vec![];
+            if !member_vectors.is_empty() {
+                for _iter in 0..I {
+                    (cluster_centroids, member_ids) =
+                        kmeans_iteration(&member_vectors, &cluster_centroids, distance);
+                }
             }
 
-            // reset cluster size for next iteration
-            cluster_sizes[id] = 0;
+            centroids.extend(cluster_centroids);
+            child_clusters.extend((0..B).map(|b| {
+                members
+                    .iter()
+                    .zip(member_ids.iter())
+                    .filter(|(_, id)| **id == b)
+                    .map(|(i, _)| *i)
+                    .collect::<Vec<usize>>()
+            }));
+        }
+
+        levels.push(centroids);
+        clusters = child_clusters;
+    }
 
-            // assign to centroid
-            centroids[id] = mean;
+    // leaf cluster id of each vector
+    let mut cluster_ids: Vec<usize> = vec![0; vectors.len()];
+    for (id, members) in clusters.iter().enumerate() {
+        for i in members {
+            cluster_ids[*i] = id;
         }
+    }
 
-        // println!("{:?}:\t{:?}\n\t{:?}", _iter, centroids, cluster_ids);
+    (levels, cluster_ids)
+}
+
+/// Same as `kmeans`, but also returns the k-means objective (sum of squared distances of each
+/// vector to its centroid) and the movement of centroids (sum of squared Euclidean distances)
+/// in the final iteration.
+pub fn kmeans_with_convergence<const K: usize, const I: usize>(
+    vectors: &Vec<Vec<f64>>,
+    distance: &dyn Fn(&Vec<f64>, &Vec<f64>) -> f64,
+) -> ([Vec<f64>; K], Vec<usize>, f64, f64) {
+    assert!(K < vectors.len(), "you must provide at least K many vectors");
+    assert!(I > 0, "expected at least one iteration");
+
+    let mut centroids: [Vec<f64>; K] =
+        vectors.iter().take(K).cloned().collect::<Vec<Vec<f64>>>().try_into().unwrap();
+    let mut previous_centroids = centroids.clone();
+    let mut cluster_ids: Vec<usize> = (0..vectors.len()).map(|_| 0).collect();
+
+    for _iter in 0..I {
+        previous_centroids = centroids.clone();
+        (centroids, cluster_ids) = kmeans_iteration(vectors, &centroids, distance);
     }
 
-    (centroids, cluster_ids)
+    let movement: f64 = previous_centroids
+        .iter()
+        .zip(centroids.iter())
+        .map(|(p, c)| p.iter().zip(c).map(|(p, c)| (c - p).powi(2)).sum::<f64>())
+        .sum();
+
+    let objective: f64 = vectors
+        .iter()
+        .zip(cluster_ids.iter())
+        .map(|(v, id)| distance(&centroids[*id], v).powi(2))
+        .sum();
+
+    (centroids, cluster_ids, objective, movement)
 }
 
 pub fn chip_kmeans<const K: usize, const I: usize>(
@@ -104,161 +325,1512 @@ pub fn chip_kmeans<const K: usize, const I: usize>(
         .map(|v| ctx.assign_witnesses(fixed_point_chip.quantize_vector(&v)))
         .collect();
 
-    let (centroids, cluster_indicators) =
-        vectordb_chip.kmeans::<K, I>(ctx, &qvectors, &|ctx, a, b| {
-            distance_chip.euclidean_distance(ctx, a, b)
-        });
+    let result = vectordb_chip
+        .kmeans::<K, I>(ctx, &qvectors, &|ctx, a, b| distance_chip.euclidean_distance(ctx, a, b));
+
+    let centroids_native: [Vec<f64>; K] =
+        result.centroids.map(|centroid| fixed_point_chip.dequantize_vector(&centroid));
+
+    let cluster_ids: Vec<usize> =
+        result.cluster_ids.iter().map(|id| id.value().get_lower_128() as usize).collect();
+
+    (centroids_native, cluster_ids)
+}
+
+/// Runs K-means within the chip over the first `len` of the given slots, where the rest are
+/// padding slots of a database with a fixed capacity.
+///
+/// Returns the centroids & the cluster id of each slot, along with whether the constraints are satisfied.
+pub fn chip_kmeans_with_length<const K: usize, const I: usize>(
+    slots: &Vec<Vec<f64>>,
+    len: usize,
+) -> ([Vec<f64>; K], Vec<usize>, bool) {
+    let mut builder = GateThreadBuilder::mock();
+    let ctx = builder.main(0);
+    let fixed_point_chip = FixedPointChip::<F, PRECISION_BITS>::default(LOOKUP_BITS);
+    let distance_chip = DistanceChip::default(&fixed_point_chip);
+    let vectordb_chip = VectorDBChip::default(&fixed_point_chip);
+
+    let qslots: Vec<Vec<AssignedValue<F>>> =
+        quantize_vectors(slots).into_iter().map(|v| ctx.assign_witnesses(v)).collect();
+    let len = ctx.load_witness(F::from(len as u64));
+
+    let result = vectordb_chip.kmeans_with_length::<K, I>(ctx, &qslots, len, &|ctx, a, b| {
+        distance_chip.euclidean_distance(ctx, a, b)
+    });
 
     let centroids_native: [Vec<f64>; K] =
-        centroids.map(|centroid| fixed_point_chip.dequantize_vector(&centroid));
+        result.centroids.map(|centroid| fixed_point_chip.dequantize_vector(&centroid));
+    let cluster_ids: Vec<usize> =
+        result.cluster_ids.iter().map(|id| id.value().get_lower_128() as usize).collect();
+
+    let satisfied = common::is_satisfied(builder, 15, LOOKUP_BITS);
+
+    (centroids_native, cluster_ids, satisfied)
+}
+
+/// Runs K-means within the chip, and returns whether the constraints are satisfied.
+pub fn chip_kmeans_is_satisfied<const K: usize, const I: usize>(vectors: &Vec<Vec<f64>>) -> bool {
+    let mut builder = GateThreadBuilder::mock();
+    let ctx = builder.main(0);
+    let fixed_point_chip = FixedPointChip::<F, PRECISION_BITS>::default(LOOKUP_BITS);
+    let distance_chip = DistanceChip::default(&fixed_point_chip);
+    let vectordb_chip = VectorDBChip::default(&fixed_point_chip);
 
-    // a vector of 1.0s and 0.0s for each vector
-    let cluster_indicators_native: Vec<[f64; K]> = cluster_indicators
-        .into_iter()
-        .map(|centroid| fixed_point_chip.dequantize_array(&centroid))
+    let qvectors: Vec<Vec<AssignedValue<F>>> =
+        quantize_vectors(vectors).into_iter().map(|v| ctx.assign_witnesses(v)).collect();
+
+    vectordb_chip
+        .kmeans::<K, I>(ctx, &qvectors, &|ctx, a, b| distance_chip.euclidean_distance(ctx, a, b));
+
+    common::is_satisfied(builder, 15, LOOKUP_BITS)
+}
+
+/// Runs K-means within the chip, and returns the boolean cluster selectors along with
+/// the cluster ids, as field elements.
+pub fn chip_kmeans_selectors<const K: usize, const I: usize>(
+    vectors: &Vec<Vec<f64>>,
+) -> (Vec<[F; K]>, Vec<F>) {
+    let mut builder = GateThreadBuilder::mock();
+    let ctx = builder.main(0);
+    let fixed_point_chip = FixedPointChip::<F, PRECISION_BITS>::default(LOOKUP_BITS);
+    let distance_chip = DistanceChip::default(&fixed_point_chip);
+    let vectordb_chip = VectorDBChip::default(&fixed_point_chip);
+
+    let qvectors: Vec<Vec<AssignedValue<F>>> = vectors
+        .iter()
+        .map(|v| ctx.assign_witnesses(fixed_point_chip.quantize_vector(&v)))
         .collect();
 
-    let cluster_ids: Vec<usize> = cluster_indicators_native
-        .into_iter()
-        .map(|cluster_indicator| {
-            // the first index that has 1 is the cluster id
-            for (i, ind) in cluster_indicator.iter().enumerate() {
-                if *ind == 1.0 {
-                    return i;
-                }
-            }
-            unreachable!("expected 1 to appear in indicator");
-        })
+    let result = vectordb_chip
+        .kmeans::<K, I>(ctx, &qvectors, &|ctx, a, b| distance_chip.euclidean_distance(ctx, a, b));
+
+    let cluster_selectors: Vec<[F; K]> =
+        result.cluster_selectors.iter().map(|selectors| selectors.map(|s| *s.value())).collect();
+    let cluster_ids: Vec<F> = result.cluster_ids.iter().map(|id| *id.value()).collect();
+
+    (cluster_selectors, cluster_ids)
+}
+
+pub fn chip_spherical_kmeans<const K: usize, const I: usize>(
+    vectors: &Vec<Vec<f64>>,
+) -> ([Vec<f64>; K], Vec<usize>) {
+    let mut builder = GateThreadBuilder::mock();
+    let ctx = builder.main(0);
+    let fixed_point_chip = FixedPointChip::<F, PRECISION_BITS>::default(LOOKUP_BITS);
+    let distance_chip = DistanceChip::default(&fixed_point_chip);
+    let vectordb_chip = VectorDBChip::default(&fixed_point_chip);
+
+    let qvectors: Vec<Vec<AssignedValue<F>>> = vectors
+        .iter()
+        .map(|v| ctx.assign_witnesses(fixed_point_chip.quantize_vector(&v)))
         .collect();
 
+    let result = vectordb_chip.spherical_kmeans::<K, I>(ctx, &qvectors, &|ctx, a, b| {
+        distance_chip.cosine_distance(ctx, a, b)
+    });
+
+    let centroids_native: [Vec<f64>; K] =
+        result.centroids.map(|centroid| fixed_point_chip.dequantize_vector(&centroid));
+
+    let cluster_ids: Vec<usize> =
+        result.cluster_ids.iter().map(|id| id.value().get_lower_128() as usize).collect();
+
     (centroids_native, cluster_ids)
 }
 
-// pub fn chip_kmeans_with_roots<const K: usize, const I: usize>(
-//     vectors: &Vec<Vec<f64>>,
-// ) -> ([Vec<f64>; K], Vec<usize>, F, F, Vec<F>) {
-//     let mut builder = GateThreadBuilder::mock();
-//     let ctx = builder.main(0);
-//     let fixed_point_chip = FixedPointChip::<F, PRECISION_BITS>::default(LOOKUP_BITS);
-//     let distance_chip = DistanceChip::default(&fixed_point_chip);
-//     let vectordb_chip = VectorDBChip::default(&fixed_point_chip);
-//     let mut poseidon_chip = PoseidonChip::<F, T, RATE>::new(ctx, R_F, R_P).unwrap();
-
-//     let qvectors: Vec<Vec<AssignedValue<F>>> = vectors
-//         .iter()
-//         .map(|v| ctx.assign_witnesses(fixed_point_chip.quantize_vector(&v)))
-//         .collect();
-
-//     let (centroids, cluster_indicators) =
-//         vectordb_chip.kmeans::<K, I>(ctx, &qvectors, &|ctx, a, b| {
-//             distance_chip.euclidean_distance(ctx, a, b)
-//         });
-
-//     let centroids_native: [Vec<f64>; K] =
-//         centroids.map(|centroid| fixed_point_chip.dequantize_vector(&centroid));
-
-//     // a vector of 1.0s and 0.0s for each vector
-//     let cluster_indicators_native: Vec<[f64; K]> = cluster_indicators
-//         .into_iter()
-//         .map(|centroid| fixed_point_chip.dequantize_array(&centroid))
-//         .collect();
-
-//     let cluster_ids: Vec<usize> = cluster_indicators_native
-//         .into_iter()
-//         .map(|cluster_indicator| {
-//             // the first index that has 1 is the cluster id
-//             for (i, ind) in cluster_indicator.iter().enumerate() {
-//                 if *ind == 1.0 {
-//                     return i;
-//                 }
-//             }
-//             unreachable!("expected 1 to appear in indicator");
-//         })
-//         .collect();
-
-//     // Merkle root over the database
-//     let vectors_root: F =
-//         *vectordb_chip.merkle_commitment::<T, RATE>(ctx, &mut poseidon_chip, &qvectors).value();
-
-//     // Merkle root over the centroids
-//     let centroids_root: F = *vectordb_chip
-//         .merkle_commitment::<T, RATE>(ctx, &mut poseidon_chip, &centroids.to_vec())
-//         .value();
-
-//     // Merkle root over the clusters
-//     let cluster_roots: Vec<F> = (0..centroids.len())
-//         .map(|cluster_id| {
-//             let cluster = common::select_cluster_within_chip(&qvectors, &cluster_ids, cluster_id);
-//             vectordb_chip.mean_merkle::<T, RATE>(ctx, &mut poseidon, &cluster, expected_mean)
-//         })
-//         .collect();
-
-//     (centroids_native, cluster_ids)
-// }
+/// Runs K-medoids within the chip with the Manhattan distance.
+///
+/// Returns the medoid indices, the cluster ids and the medoids, along with whether the
+/// constraints are satisfied.
+pub fn chip_kmedoids<const K: usize, const I: usize>(
+    vectors: &Vec<Vec<f64>>,
+) -> ([usize; K], Vec<usize>, [Vec<f64>; K], bool) {
+    let mut builder = GateThreadBuilder::mock();
+    let ctx = builder.main(0);
+    let fixed_point_chip = FixedPointChip::<F, PRECISION_BITS>::default(LOOKUP_BITS);
+    let distance_chip = DistanceChip::default(&fixed_point_chip);
+    let vectordb_chip = VectorDBChip::default(&fixed_point_chip);
 
-/// An exhaustive search to find the most similar vector among a database to a given query vector.
+    let qvectors: Vec<Vec<AssignedValue<F>>> = vectors
+        .iter()
+        .map(|v| ctx.assign_witnesses(fixed_point_chip.quantize_vector(&v)))
+        .collect();
+
+    let result = vectordb_chip
+        .kmedoids::<K, I>(ctx, &qvectors, &|ctx, a, b| distance_chip.manhattan_distance(ctx, a, b));
+
+    let medoid_indices: [usize; K] =
+        result.medoid_indices.map(|idx| idx.value().get_lower_128() as usize);
+    let cluster_ids: Vec<usize> =
+        result.cluster_ids.iter().map(|id| id.value().get_lower_128() as usize).collect();
+    let medoids_native: [Vec<f64>; K] =
+        result.medoids.map(|medoid| fixed_point_chip.dequantize_vector(&medoid));
+
+    let satisfied = common::is_satisfied(builder, 15, LOOKUP_BITS);
+    (medoid_indices, cluster_ids, medoids_native, satisfied)
+}
+
+/// Runs hierarchical K-means within the chip with respect to the given `metric` (either
+/// `euclidean` or `cosine`), committing to each level with the given `scheme`.
 ///
-/// The respective distance function is given as a parameter.
-pub fn nearest_vector(
-    query: &Vec<f64>,
+/// Returns the centroids at each level, the leaf cluster ids and whether each level root
+/// matches the native Merkle root over the centroids of that level, along with whether the
+/// constraints are satisfied.
+pub fn chip_hierarchical_kmeans<const B: usize, const I: usize>(
     vectors: &Vec<Vec<f64>>,
-    distance: &dyn Fn(&Vec<f64>, &Vec<f64>) -> f64,
-) -> (usize, Vec<f64>) {
-    let distances: Vec<f64> = vectors.iter().map(|v| distance(v, query)).collect();
+    depth: usize,
+    metric: &str,
+    scheme: CommitmentScheme,
+) -> (Vec<Vec<Vec<f64>>>, Vec<usize>, bool, bool) {
+    let mut builder = GateThreadBuilder::mock();
+    let ctx = builder.main(0);
+    let fixed_point_chip = FixedPointChip::<F, PRECISION_BITS>::default(LOOKUP_BITS);
+    let distance_chip = DistanceChip::default(&fixed_point_chip);
+    let vectordb_chip = VectorDBChip::default(&fixed_point_chip);
+    let mut poseidon_chip = PARAMS.poseidon_chip(ctx);
 
-    let min = distances.iter().fold(f64::INFINITY, |a, &b| a.min(b));
+    let qvectors: Vec<Vec<AssignedValue<F>>> = vectors
+        .iter()
+        .map(|v| ctx.assign_witnesses(fixed_point_chip.quantize_vector(&v)))
+        .collect();
 
-    vectors
+    let result = vectordb_chip.hierarchical_kmeans::<B, I, T, RATE>(
+        ctx,
+        &mut poseidon_chip,
+        scheme,
+        &qvectors,
+        depth,
+        &|ctx, a, b| match metric {
+            "euclidean" => distance_chip.euclidean_distance(ctx, a, b),
+            "cosine" => distance_chip.cosine_distance(ctx, a, b),
+            _ => panic!("unknown metric {}", metric),
+        },
+    );
+
+    let levels_native: Vec<Vec<Vec<f64>>> = result
+        .levels
         .iter()
-        .enumerate()
-        .find(|(i, _)| min == distances[*i])
-        .and_then(|(i, v)| Some((i, v.clone())))
-        .expect("should have found a minimum")
-        .to_owned()
+        .map(|centroids| centroids.iter().map(|c| fixed_point_chip.dequantize_vector(c)).collect())
+        .collect();
+    let cluster_ids: Vec<usize> =
+        result.cluster_ids.iter().map(|id| id.value().get_lower_128() as usize).collect();
+    let roots_match =
+        result.levels.iter().zip(result.level_roots.iter()).all(|(centroids, root)| {
+            let centroids: Vec<Vec<F>> =
+                centroids.iter().map(|c| c.iter().map(|x| *x.value()).collect()).collect();
+            native_merkle_tree(&centroids, scheme).root() == *root.value()
+        });
+
+    let satisfied = common::is_satisfied(builder, 16, LOOKUP_BITS);
+    (levels_native, cluster_ids, roots_match, satisfied)
 }
 
-pub fn chip_nearest_vector(query: &Vec<f64>, vectors: &Vec<Vec<f64>>) -> (usize, Vec<f64>, F) {
+/// Runs K-means within the chip, and returns the objective & movement of centroids in the
+/// final iteration along with the centroids.
+pub fn chip_kmeans_with_convergence<const K: usize, const I: usize>(
+    vectors: &Vec<Vec<f64>>,
+) -> ([Vec<f64>; K], f64, f64) {
     let mut builder = GateThreadBuilder::mock();
     let ctx = builder.main(0);
     let fixed_point_chip = FixedPointChip::<F, PRECISION_BITS>::default(LOOKUP_BITS);
     let distance_chip = DistanceChip::default(&fixed_point_chip);
     let vectordb_chip = VectorDBChip::default(&fixed_point_chip);
-    let mut poseidon_chip = PoseidonChip::<F, T, RATE>::new(ctx, R_F, R_P).unwrap();
 
-    let qquery: Vec<AssignedValue<F>> = fixed_point_chip.quantize_and_assign_vector(ctx, query);
     let qvectors: Vec<Vec<AssignedValue<F>>> = vectors
         .iter()
         .map(|v| ctx.assign_witnesses(fixed_point_chip.quantize_vector(&v)))
         .collect();
 
-    let (indicator, result) =
-        vectordb_chip.nearest_vector(ctx, &qquery, &qvectors, &|ctx, a, b| {
+    let (result, convergence) =
+        vectordb_chip.kmeans_with_convergence::<K, I>(ctx, &qvectors, &|ctx, a, b| {
             distance_chip.euclidean_distance(ctx, a, b)
         });
 
-    let result = fixed_point_chip.dequantize_vector(&result);
-    let index = indicator
+    let centroids_native: [Vec<f64>; K] =
+        result.centroids.map(|centroid| fixed_point_chip.dequantize_vector(&centroid));
+    let objective = fixed_point_chip.dequantization(*convergence.objective.value());
+    let movement = fixed_point_chip.dequantization(*convergence.movement.value());
+
+    (centroids_native, objective, movement)
+}
+
+/// Computes the K-means objective within the chip w.r.t. the given centroids and cluster ids,
+/// with the Euclidean distance.
+///
+/// Returns the objective, along with whether the constraints are satisfied.
+pub fn chip_kmeans_objective<const K: usize>(
+    vectors: &Vec<Vec<f64>>,
+    centroids: &[Vec<f64>; K],
+    cluster_ids: &Vec<usize>,
+) -> (f64, bool) {
+    let mut builder = GateThreadBuilder::mock();
+    let ctx = builder.main(0);
+    let fixed_point_chip = FixedPointChip::<F, PRECISION_BITS>::default(LOOKUP_BITS);
+    let distance_chip = DistanceChip::default(&fixed_point_chip);
+    let vectordb_chip = VectorDBChip::default(&fixed_point_chip);
+
+    let qvectors: Vec<Vec<AssignedValue<F>>> =
+        quantize_vectors(vectors).into_iter().map(|v| ctx.assign_witnesses(v)).collect();
+    let qcentroids: [Vec<AssignedValue<F>>; K] =
+        centroids.clone().map(|c| ctx.assign_witnesses(fixed_point_chip.quantize_vector(&c)));
+    let cluster_selectors: Vec<[AssignedValue<F>; K]> = cluster_ids
         .iter()
-        .position(|v| common::compare_fields::<F>(v.value(), &F::one()))
-        .expect("expected 1");
+        .map(|id| core::array::from_fn(|k| ctx.load_witness(F::from((k == *id) as u64))))
+        .collect();
 
-    let root = vectordb_chip.merkle_commitment::<T, RATE>(ctx, &mut poseidon_chip, &qvectors);
+    let objective = vectordb_chip.kmeans_objective::<K>(
+        ctx,
+        &qvectors,
+        &qcentroids,
+        &cluster_selectors,
+        &|ctx, a, b| distance_chip.euclidean_distance(ctx, a, b),
+    );
+    let objective = fixed_point_chip.dequantization(*objective.value());
 
-    (index, result, *root.value())
+    (objective, common::is_satisfied(builder, 15, LOOKUP_BITS))
 }
 
-pub fn chip_merkle(vectors: &Vec<Vec<f64>>) -> F {
+/// Runs K-means within the chip, and commits to the database, the centroids and each cluster,
+/// where clusters are bound to their centroids & the database.
+///
+/// If `tamper` is set, the largest cluster is replaced with another set of vectors that has the
+/// same mean, which should not satisfy the constraints.
+///
+/// Returns the centroids, cluster ids and Merkle roots (database, centroids and clusters), along with
+/// whether the constraints are satisfied.
+pub fn chip_kmeans_with_roots<const K: usize, const I: usize>(
+    vectors: &Vec<Vec<f64>>,
+    tamper: bool,
+    scheme: CommitmentScheme,
+) -> ([Vec<f64>; K], Vec<usize>, F, F, Vec<F>, bool) {
     let mut builder = GateThreadBuilder::mock();
     let ctx = builder.main(0);
     let fixed_point_chip = FixedPointChip::<F, PRECISION_BITS>::default(LOOKUP_BITS);
+    let distance_chip = DistanceChip::default(&fixed_point_chip);
     let vectordb_chip = VectorDBChip::default(&fixed_point_chip);
-    let mut poseidon_chip = PoseidonChip::<F, T, RATE>::new(ctx, R_F, R_P).unwrap();
+    let mut poseidon_chip = PARAMS.poseidon_chip(ctx);
 
     let qvectors: Vec<Vec<AssignedValue<F>>> = vectors
         .iter()
         .map(|v| ctx.assign_witnesses(fixed_point_chip.quantize_vector(&v)))
         .collect();
 
-    let root = vectordb_chip.merkle_commitment(ctx, &mut poseidon_chip, &qvectors);
-    *root.value()
+    let result = vectordb_chip
+        .kmeans::<K, I>(ctx, &qvectors, &|ctx, a, b| distance_chip.euclidean_distance(ctx, a, b));
+
+    let centroids_native: [Vec<f64>; K] =
+        result.centroids.clone().map(|centroid| fixed_point_chip.dequantize_vector(&centroid));
+    let cluster_ids: Vec<usize> =
+        result.cluster_ids.iter().map(|id| id.value().get_lower_128() as usize).collect();
+
+    // Merkle root over the database
+    let vectors_root: F =
+        *vectordb_chip.merkle_commitment::<T, RATE>(ctx, &mut poseidon_chip, &qvectors).value();
+
+    // Merkle root over the centroids
+    let centroids_root: F = *vectordb_chip
+        .merkle_commitment::<T, RATE>(ctx, &mut poseidon_chip, &result.centroids.to_vec())
+        .value();
+
+    // Merkle root over the clusters
+    let largest_cluster_id = (0..K)
+        .max_by_key(|cluster_id| cluster_ids.iter().filter(|id| *id == cluster_id).count())
+        .unwrap();
+    let cluster_roots: Vec<F> = (0..K)
+        .map(|cluster_id| {
+            let mut cluster =
+                common::select_cluster_within_chip(&qvectors, &cluster_ids, cluster_id);
+
+            if tamper && cluster_id == largest_cluster_id {
+                // move two vectors in opposite directions, keeping the same mean
+                let delta = fixed_point_chip.quantization(0.5);
+                cluster[0][0] = ctx.load_witness(*cluster[0][0].value() + delta);
+                cluster[1][0] = ctx.load_witness(*cluster[1][0].value() - delta);
+            }
+
+            let selectors: Vec<AssignedValue<F>> =
+                result.cluster_selectors.iter().map(|selectors| selectors[cluster_id]).collect();
+            vectordb_chip.assert_cluster_membership(ctx, &qvectors, &selectors, &cluster);
+
+            let root = vectordb_chip.mean_merkle::<T, RATE>(
+                ctx,
+                &mut poseidon_chip,
+                scheme,
+                &cluster,
+                &result.centroids[cluster_id],
+            );
+            *root.value()
+        })
+        .collect();
+
+    let satisfied = common::is_satisfied(builder, 16, LOOKUP_BITS);
+
+    (centroids_native, cluster_ids, vectors_root, centroids_root, cluster_roots, satisfied)
+}
+
+/// Asserts that `cluster` consists of the vectors selected by `selectors` within the chip,
+/// where the selectors are given as field elements, and returns whether the constraints are
+/// satisfied.
+pub fn chip_cluster_membership(
+    vectors: &Vec<Vec<f64>>,
+    selectors: &Vec<F>,
+    cluster: &Vec<Vec<f64>>,
+) -> bool {
+    let mut builder = GateThreadBuilder::mock();
+    let ctx = builder.main(0);
+    let fixed_point_chip = FixedPointChip::<F, PRECISION_BITS>::default(LOOKUP_BITS);
+    let vectordb_chip = VectorDBChip::default(&fixed_point_chip);
+
+    let qvectors: Vec<Vec<AssignedValue<F>>> =
+        quantize_vectors(vectors).into_iter().map(|v| ctx.assign_witnesses(v)).collect();
+    let qcluster: Vec<Vec<AssignedValue<F>>> =
+        quantize_vectors(cluster).into_iter().map(|v| ctx.assign_witnesses(v)).collect();
+    let selectors = ctx.assign_witnesses(selectors.clone());
+
+    vectordb_chip.assert_cluster_membership(ctx, &qvectors, &selectors, &qcluster);
+
+    common::is_satisfied(builder, 14, LOOKUP_BITS)
+}
+
+/// Builds an index with K-means within the chip, and commits to the database, the centroids and
+/// each cluster within the same circuit, proving that the clusters partition the database.
+///
+/// Returns the centroids, cluster ids and Merkle roots (database, centroids and clusters) with
+/// respect to the commitment `scheme`, along with whether the constraints are satisfied.
+pub fn chip_index<const K: usize, const I: usize>(
+    vectors: &Vec<Vec<f64>>,
+    scheme: CommitmentScheme,
+) -> ([Vec<f64>; K], Vec<usize>, F, F, Vec<F>, bool) {
+    let (builder, centroids, cluster_ids, database_root, centroids_root, cluster_roots) =
+        index::<K, I>(vectors, scheme);
+
+    let satisfied = common::is_satisfied(builder, 16, LOOKUP_BITS);
+
+    (centroids, cluster_ids, database_root, centroids_root, cluster_roots, satisfied)
+}
+
+/// Builds the circuit of `chip_index` without running the mock prover, which is too costly
+/// for large databases when only the witnesses are needed (e.g. in the demo).
+///
+/// Returns the builder along with the outputs of `chip_index`.
+pub fn index<const K: usize, const I: usize>(
+    vectors: &Vec<Vec<f64>>,
+    scheme: CommitmentScheme,
+) -> (GateThreadBuilder<F>, [Vec<f64>; K], Vec<usize>, F, F, Vec<F>) {
+    let mut builder = GateThreadBuilder::mock();
+    let ctx = builder.main(0);
+    let fixed_point_chip = FixedPointChip::<F, PRECISION_BITS>::default(LOOKUP_BITS);
+    let distance_chip = DistanceChip::default(&fixed_point_chip);
+    let vectordb_chip = VectorDBChip::default(&fixed_point_chip);
+    let mut poseidon_chip = PARAMS.poseidon_chip(ctx);
+
+    let qvectors: Vec<Vec<AssignedValue<F>>> = vectors
+        .iter()
+        .map(|v| ctx.assign_witnesses(fixed_point_chip.quantize_vector(&v)))
+        .collect();
+
+    let result = vectordb_chip
+        .kmeans::<K, I>(ctx, &qvectors, &|ctx, a, b| distance_chip.euclidean_distance(ctx, a, b));
+
+    let centroids_native: [Vec<f64>; K] =
+        result.centroids.clone().map(|centroid| fixed_point_chip.dequantize_vector(&centroid));
+    let cluster_ids: Vec<usize> =
+        result.cluster_ids.iter().map(|id| id.value().get_lower_128() as usize).collect();
+
+    let clusters: [Vec<Vec<AssignedValue<F>>>; K] = core::array::from_fn(|cluster_id| {
+        common::select_cluster_within_chip(&qvectors, &cluster_ids, cluster_id)
+    });
+    let commitment = vectordb_chip.index_commitment(
+        ctx,
+        &mut poseidon_chip,
+        scheme,
+        &qvectors,
+        &result,
+        &clusters,
+    );
+
+    let database_root = *commitment.database_root.value();
+    let centroids_root = *commitment.centroids_root.value();
+    let cluster_roots: Vec<F> = commitment.cluster_roots.iter().map(|root| *root.value()).collect();
+
+    (builder, centroids_native, cluster_ids, database_root, centroids_root, cluster_roots)
+}
+
+/// An exhaustive search to find the most similar vector among a database to a given query vector.
+///
+/// The respective distance function is given as a parameter.
+pub fn nearest_vector(
+    query: &Vec<f64>,
+    vectors: &Vec<Vec<f64>>,
+    distance: &dyn Fn(&Vec<f64>, &Vec<f64>) -> f64,
+) -> (usize, Vec<f64>) {
+    let distances: Vec<f64> = vectors.iter().map(|v| distance(v, query)).collect();
+
+    let min = distances.iter().fold(f64::INFINITY, |a, &b| a.min(b));
+
+    vectors
+        .iter()
+        .enumerate()
+        .find(|(i, _)| min == distances[*i])
+        .and_then(|(i, v)| Some((i, v.clone())))
+        .expect("should have found a minimum")
+        .to_owned()
+}
+
+/// An exhaustive search to find the indices of the `k` most similar vectors among a database
+/// to a given query vector, in increasing order of distance (ties broken by the lowest index).
+pub fn top_k(
+    query: &Vec<f64>,
+    vectors: &Vec<Vec<f64>>,
+    k: usize,
+    distance: &dyn Fn(&Vec<f64>, &Vec<f64>) -> f64,
+) -> Vec<usize> {
+    let distances: Vec<f64> = vectors.iter().map(|v| distance(v, query)).collect();
+
+    let mut indices: Vec<usize> = (0..vectors.len()).collect();
+    indices.sort_by(|a, b| distances[*a].partial_cmp(&distances[*b]).unwrap());
+    indices.truncate(k);
+    indices
+}
+
+/// Finds the `K` nearest entries within the chip along with their metadata w.r.t. the given
+/// `metric` (either `euclidean` or `manhattan`), and commits to the database with metadata bound
+/// to each leaf (with the domain-separated scheme).
+///
+/// Returns the index & metadata of each result, the chip root and the native root, along with
+/// whether the constraints are satisfied.
+pub fn chip_top_k<const K: usize>(
+    query: &Vec<f64>,
+    vectors: &Vec<Vec<f64>>,
+    metadata: &Vec<Vec<u64>>,
+    metric: &str,
+) -> (Vec<usize>, Vec<Vec<u64>>, F, F, bool) {
+    let mut builder = GateThreadBuilder::mock();
+    let ctx = builder.main(0);
+    let fixed_point_chip = FixedPointChip::<F, PRECISION_BITS>::default(LOOKUP_BITS);
+    let distance_chip = DistanceChip::default(&fixed_point_chip);
+    let vectordb_chip = VectorDBChip::default(&fixed_point_chip);
+    let mut poseidon_chip = PARAMS.poseidon_chip(ctx);
+    let params = CommitmentParams { scheme: CommitmentScheme::DomainSeparated, ..PARAMS };
+
+    let qvectors = quantize_vectors(vectors);
+    let qmetadata: Vec<Vec<F>> =
+        metadata.iter().map(|m| m.iter().map(|x| F::from(*x)).collect()).collect();
+    let native_root = MerkleTree::new_with_metadata(&qvectors, &qmetadata, &params).root();
+
+    let qquery: Vec<AssignedValue<F>> = fixed_point_chip.quantize_and_assign_vector(ctx, query);
+    let assigned_vectors: Vec<Vec<AssignedValue<F>>> =
+        qvectors.iter().map(|v| ctx.assign_witnesses(v.clone())).collect();
+    let assigned_metadata: Vec<Vec<AssignedValue<F>>> =
+        qmetadata.iter().map(|m| ctx.assign_witnesses(m.clone())).collect();
+
+    let results = vectordb_chip.top_k::<K>(
+        ctx,
+        &qquery,
+        &assigned_vectors,
+        &assigned_metadata,
+        &|ctx, a, b| match metric {
+            "euclidean" => distance_chip.euclidean_distance(ctx, a, b),
+            "manhattan" => distance_chip.manhattan_distance(ctx, a, b),
+            _ => panic!("unknown metric {}", metric),
+        },
+    );
+    let root = vectordb_chip.merkle_commitment_with_metadata(
+        ctx,
+        &mut poseidon_chip,
+        params.scheme,
+        &assigned_vectors,
+        &assigned_metadata,
+    );
+
+    let indices: Vec<usize> = results
+        .iter()
+        .map(|result| {
+            result
+                .indicator
+                .iter()
+                .position(|v| common::compare_fields::<F>(v.value(), &F::one()))
+                .expect("expected 1")
+        })
+        .collect();
+    let metadata: Vec<Vec<u64>> = results
+        .iter()
+        .map(|result| result.metadata.iter().map(|m| m.value().get_lower_128() as u64).collect())
+        .collect();
+    let root = *root.value();
+
+    let satisfied = common::is_satisfied(builder, 14, LOOKUP_BITS);
+
+    (indices, metadata, root, native_root, satisfied)
+}
+
+/// Finds the nearest vector among those whose `attributes` satisfy all of the `conditions`
+/// within the chip, where each condition is a field index, a comparison and a value, with
+/// respect to the given `metric` (either `euclidean` or `manhattan`).
+///
+/// Returns the index of the result if any vector matches, along with whether the constraints
+/// are satisfied.
+pub fn chip_filtered_nearest_vector(
+    query: &Vec<f64>,
+    vectors: &Vec<Vec<f64>>,
+    attributes: &Vec<Vec<u64>>,
+    conditions: &[(usize, Comparison, u64)],
+    metric: &str,
+) -> (Option<usize>, bool) {
+    let mut builder = GateThreadBuilder::mock();
+    let ctx = builder.main(0);
+    let fixed_point_chip = FixedPointChip::<F, PRECISION_BITS>::default(LOOKUP_BITS);
+    let distance_chip = DistanceChip::default(&fixed_point_chip);
+    let vectordb_chip = VectorDBChip::default(&fixed_point_chip);
+
+    let qquery: Vec<AssignedValue<F>> = fixed_point_chip.quantize_and_assign_vector(ctx, query);
+    let qvectors: Vec<Vec<AssignedValue<F>>> = vectors
+        .iter()
+        .map(|v| ctx.assign_witnesses(fixed_point_chip.quantize_vector(&v)))
+        .collect();
+    let attributes: Vec<Vec<AssignedValue<F>>> =
+        attributes.iter().map(|a| ctx.assign_witnesses(a.iter().map(|x| F::from(*x)))).collect();
+    let conditions: Vec<Condition<F>> = conditions
+        .iter()
+        .map(|(field, comparison, value)| Condition {
+            field: *field,
+            comparison: *comparison,
+            value: ctx.load_witness(F::from(*value)),
+        })
+        .collect();
+
+    let (result, found) = vectordb_chip.filtered_nearest_vector(
+        ctx,
+        &qquery,
+        &qvectors,
+        &attributes,
+        &conditions,
+        &|ctx, a, b| match metric {
+            "euclidean" => distance_chip.euclidean_distance(ctx, a, b),
+            "manhattan" => distance_chip.manhattan_distance(ctx, a, b),
+            _ => panic!("unknown metric {}", metric),
+        },
+    );
+
+    let index = if *found.value() == F::one() {
+        result.indicator.iter().position(|v| common::compare_fields::<F>(v.value(), &F::one()))
+    } else {
+        None
+    };
+
+    let satisfied = common::is_satisfied(builder, 14, LOOKUP_BITS);
+
+    (index, satisfied)
+}
+
+/// Finds the vectors within distance `threshold` of the query within the chip, with respect to
+/// the given `metric` (one of `euclidean`, `cosine`, `manhattan` or `hamming`).
+///
+/// Returns the membership of each vector, the number of matches & whether the commitment to
+/// the matches is the same as the native one, along with whether the constraints are satisfied.
+pub fn chip_range_query(
+    query: &Vec<f64>,
+    vectors: &Vec<Vec<f64>>,
+    threshold: f64,
+    metric: &str,
+) -> (Vec<bool>, u64, bool, bool) {
+    let mut builder = GateThreadBuilder::mock();
+    let ctx = builder.main(0);
+    let fixed_point_chip = FixedPointChip::<F, PRECISION_BITS>::default(LOOKUP_BITS);
+    let distance_chip = DistanceChip::default(&fixed_point_chip);
+    let vectordb_chip = VectorDBChip::default(&fixed_point_chip);
+    let mut poseidon_chip = PARAMS.poseidon_chip(ctx);
+    let params = CommitmentParams { scheme: CommitmentScheme::DomainSeparated, ..PARAMS };
+
+    let qvectors = quantize_vectors(vectors);
+    let qquery: Vec<AssignedValue<F>> = fixed_point_chip.quantize_and_assign_vector(ctx, query);
+    let assigned_vectors: Vec<Vec<AssignedValue<F>>> =
+        qvectors.iter().map(|v| ctx.assign_witnesses(v.clone())).collect();
+    let threshold = ctx.load_witness(fixed_point_chip.quantization(threshold));
+
+    let distance =
+        |ctx: &mut Context<F>, a: &Vec<AssignedValue<F>>, b: &Vec<AssignedValue<F>>| match metric {
+            "euclidean" => distance_chip.euclidean_distance(ctx, a, b),
+            "cosine" => distance_chip.cosine_distance(ctx, a, b),
+            "manhattan" => distance_chip.manhattan_distance(ctx, a, b),
+            "hamming" => distance_chip.hamming_distance(ctx, a, b),
+            _ => panic!("unknown metric {}", metric),
+        };
+    let result = vectordb_chip.range_query(
+        ctx,
+        &mut poseidon_chip,
+        params.scheme,
+        &qquery,
+        &assigned_vectors,
+        threshold,
+        &distance,
+    );
+
+    let members: Vec<bool> = result.members.iter().map(|m| *m.value() == F::one()).collect();
+    let count = result.count.value().get_lower_128() as u64;
+    let commitment_matches =
+        *result.commitment.value() == merkle::range_commitment(&qvectors, &members, &params);
+
+    let satisfied = common::is_satisfied(builder, 15, LOOKUP_BITS);
+
+    (members, count, commitment_matches, satisfied)
+}
+
+/// Proves within the chip whether the query matches some vector within distance `threshold`,
+/// with respect to the commitment `scheme`.
+///
+/// Returns the boolean, the query commitment, whether the database root & the query commitment
+/// are the same as the native ones, along with whether the constraints are satisfied.
+pub fn chip_threshold_match(
+    query: &Vec<f64>,
+    vectors: &Vec<Vec<f64>>,
+    threshold: f64,
+    salt: u128,
+    scheme: CommitmentScheme,
+) -> (bool, F, bool, bool) {
+    let mut builder = GateThreadBuilder::mock();
+    let ctx = builder.main(0);
+    let fixed_point_chip = FixedPointChip::<F, PRECISION_BITS>::default(LOOKUP_BITS);
+    let distance_chip = DistanceChip::default(&fixed_point_chip);
+    let vectordb_chip = VectorDBChip::default(&fixed_point_chip);
+    let mut poseidon_chip = PARAMS.poseidon_chip(ctx);
+    let params = CommitmentParams { scheme, ..PARAMS };
+    let salt = F::from_u128(salt);
+
+    let qvectors = quantize_vectors(vectors);
+    let qquery = fixed_point_chip.quantize_vector(query);
+    let assigned_query = ctx.assign_witnesses(qquery.clone());
+    let assigned_salt = ctx.load_witness(salt);
+    let assigned_vectors: Vec<Vec<AssignedValue<F>>> =
+        qvectors.iter().map(|v| ctx.assign_witnesses(v.clone())).collect();
+    let threshold = ctx.load_constant(fixed_point_chip.quantization(threshold));
+
+    let result = vectordb_chip.threshold_match(
+        ctx,
+        &mut poseidon_chip,
+        params.scheme,
+        &assigned_query,
+        assigned_salt,
+        &assigned_vectors,
+        threshold,
+        &|ctx, a, b| distance_chip.euclidean_distance(ctx, a, b),
+    );
+
+    let matched = *result.matched.value() == F::one();
+    let commitment = *result.query_commitment.value();
+    let roots_match = *result.database_root.value() == MerkleTree::new(&qvectors, &params).root()
+        && commitment == commit_query(&qquery, salt, &params);
+
+    let satisfied = common::is_satisfied(builder, 14, LOOKUP_BITS);
+
+    (matched, commitment, roots_match, satisfied)
+}
+
+/// Commits to the query with the given `salt` & hashes the nearest vector with the same salt
+/// within the chip.
+///
+/// Returns whether both are the same as the native ones, along with the query commitment.
+pub fn chip_query_commitment(query: &Vec<f64>, vectors: &Vec<Vec<f64>>, salt: u128) -> (bool, F) {
+    let mut builder = GateThreadBuilder::mock();
+    let ctx = builder.main(0);
+    let fixed_point_chip = FixedPointChip::<F, PRECISION_BITS>::default(LOOKUP_BITS);
+    let distance_chip = DistanceChip::default(&fixed_point_chip);
+    let vectordb_chip = VectorDBChip::default(&fixed_point_chip);
+    let mut poseidon_chip = PARAMS.poseidon_chip(ctx);
+
+    let qquery = fixed_point_chip.quantize_vector(query);
+    let assigned_query = ctx.assign_witnesses(qquery.clone());
+    let assigned_vectors: Vec<Vec<AssignedValue<F>>> =
+        quantize_vectors(vectors).into_iter().map(|v| ctx.assign_witnesses(v)).collect();
+    let salt = ctx.load_witness(F::from_u128(salt));
+
+    let (_, result) =
+        vectordb_chip.nearest_vector(ctx, &assigned_query, &assigned_vectors, &|ctx, a, b| {
+            distance_chip.euclidean_distance(ctx, a, b)
+        });
+    let commitment = vectordb_chip.commit_query(ctx, &mut poseidon_chip, &assigned_query, salt);
+    let raw =
+        vectordb_chip.public_result(ctx, &mut poseidon_chip, ResultVisibility::Raw, &result, salt);
+    let hashed = vectordb_chip.public_result(
+        ctx,
+        &mut poseidon_chip,
+        ResultVisibility::Hashed,
+        &result,
+        salt,
+    );
+
+    let result: Vec<F> = result.iter().map(|r| *r.value()).collect();
+    let matches = *commitment.value() == commit_query(&qquery, *salt.value(), &PARAMS)
+        && raw.iter().map(|r| *r.value()).collect::<Vec<F>>() == result
+        && hashed.len() == 1
+        && *hashed[0].value() == hash_result(&result, *salt.value(), &PARAMS);
+
+    (matches, *commitment.value())
+}
+
+/// Finds the nearest vector (w.r.t. cosine distance) for each query in a single circuit, sharing
+/// the norms of the database vectors across queries.
+///
+/// Returns the index of each result & the database root, along with whether the constraints
+/// are satisfied.
+pub fn chip_batch_nearest_vector(
+    queries: &Vec<Vec<f64>>,
+    vectors: &Vec<Vec<f64>>,
+) -> (Vec<usize>, F, bool) {
+    let mut builder = GateThreadBuilder::mock();
+    let ctx = builder.main(0);
+    let fixed_point_chip = FixedPointChip::<F, PRECISION_BITS>::default(LOOKUP_BITS);
+    let distance_chip = DistanceChip::default(&fixed_point_chip);
+    let vectordb_chip = VectorDBChip::default(&fixed_point_chip);
+    let mut poseidon_chip = PARAMS.poseidon_chip(ctx);
+
+    let qqueries: Vec<Vec<AssignedValue<F>>> =
+        queries.iter().map(|q| fixed_point_chip.quantize_and_assign_vector(ctx, q)).collect();
+    let qvectors: Vec<Vec<AssignedValue<F>>> =
+        quantize_vectors(vectors).into_iter().map(|v| ctx.assign_witnesses(v)).collect();
+
+    let batch = vectordb_chip.batch_nearest_vector(
+        ctx,
+        &mut poseidon_chip,
+        PARAMS.scheme,
+        &qqueries,
+        &qvectors,
+        &|ctx, v| distance_chip.norm(ctx, v),
+        &|ctx, v, v_norm, q, q_norm| {
+            distance_chip.cosine_distance_with_norms(ctx, v, q, v_norm, q_norm)
+        },
+    );
+
+    let indices: Vec<usize> = batch
+        .indicators
+        .iter()
+        .map(|indicator| {
+            indicator
+                .iter()
+                .position(|v| common::compare_fields::<F>(v.value(), &F::one()))
+                .expect("expected 1")
+        })
+        .collect();
+    let root = *batch.database_root.value();
+
+    let satisfied = common::is_satisfied(builder, 15, LOOKUP_BITS);
+
+    (indices, root, satisfied)
+}
+
+/// Computes the IVF root natively with respect to the commitment `scheme`, i.e. a Merkle tree
+/// over the centroids where each leaf also binds the root of its cluster.
+pub fn native_ivf_root(
+    centroids: &Vec<Vec<f64>>,
+    cluster_roots: &Vec<F>,
+    scheme: CommitmentScheme,
+) -> F {
+    let metadata: Vec<Vec<F>> = cluster_roots.iter().map(|root| vec![*root]).collect();
+    let params = CommitmentParams { scheme, ..PARAMS };
+    MerkleTree::new_with_metadata(&quantize_vectors(centroids), &metadata, &params).root()
+}
+
+/// Answers a query against a two-level (IVF) index within a single circuit with respect to the
+/// commitment `scheme`, where `cluster` is the cluster that is opened by the prover.
+///
+/// Returns the index of the selected centroid, the index & value of the result within the cluster
+/// and the IVF root, along with whether the constraints are satisfied.
+pub fn chip_ivf_query(
+    query: &Vec<f64>,
+    centroids: &Vec<Vec<f64>>,
+    cluster_roots: &Vec<F>,
+    cluster: &Vec<Vec<f64>>,
+    scheme: CommitmentScheme,
+) -> (usize, usize, Vec<f64>, F, bool) {
+    let mut builder = GateThreadBuilder::mock();
+    let ctx = builder.main(0);
+    let fixed_point_chip = FixedPointChip::<F, PRECISION_BITS>::default(LOOKUP_BITS);
+    let distance_chip = DistanceChip::default(&fixed_point_chip);
+    let vectordb_chip = VectorDBChip::default(&fixed_point_chip);
+    let mut poseidon_chip = PARAMS.poseidon_chip(ctx);
+
+    let qquery: Vec<AssignedValue<F>> = fixed_point_chip.quantize_and_assign_vector(ctx, query);
+    let qcentroids: Vec<Vec<AssignedValue<F>>> =
+        quantize_vectors(centroids).into_iter().map(|v| ctx.assign_witnesses(v)).collect();
+    let qroots: Vec<AssignedValue<F>> = ctx.assign_witnesses(cluster_roots.clone());
+    let qcluster: Vec<Vec<AssignedValue<F>>> =
+        quantize_vectors(cluster).into_iter().map(|v| ctx.assign_witnesses(v)).collect();
+
+    let ivf = vectordb_chip.ivf_query(
+        ctx,
+        &mut poseidon_chip,
+        scheme,
+        &qquery,
+        &qcentroids,
+        &qroots,
+        &qcluster,
+        &|ctx, a, b| distance_chip.euclidean_distance(ctx, a, b),
+    );
+
+    let position = |indicator: &Vec<AssignedValue<F>>| {
+        indicator
+            .iter()
+            .position(|v| common::compare_fields::<F>(v.value(), &F::one()))
+            .expect("expected 1")
+    };
+    let cluster_id = position(&ivf.centroid_indicator);
+    let index = position(&ivf.indicator);
+    let result = fixed_point_chip.dequantize_vector(&ivf.result);
+    let root = *ivf.ivf_root.value();
+
+    let satisfied = common::is_satisfied(builder, 16, LOOKUP_BITS);
+
+    (cluster_id, index, result, root, satisfied)
+}
+
+/// Same as `chip_ivf_query`, but probes the `P` nearest centroids with respect to the given
+/// `metric`, where `clusters` are the clusters that are opened by the prover.
+///
+/// Returns the indices of the probed centroids, the index (into the union of the clusters) & value
+/// of the result and the IVF root, along with whether the constraints are satisfied.
+pub fn chip_ivf_query_multi_probe<const P: usize>(
+    query: &Vec<f64>,
+    centroids: &Vec<Vec<f64>>,
+    cluster_roots: &Vec<F>,
+    clusters: &[Vec<Vec<f64>>; P],
+    scheme: CommitmentScheme,
+    metric: &str,
+) -> (Vec<usize>, usize, Vec<f64>, F, bool) {
+    let (builder, cluster_ids, index, result, root) =
+        ivf_query_multi_probe::<P>(query, centroids, cluster_roots, clusters, scheme, metric);
+
+    let satisfied = common::is_satisfied(builder, 16, LOOKUP_BITS);
+
+    (cluster_ids, index, result, *root.value(), satisfied)
+}
+
+/// Same as `chip_ivf_query_multi_probe`, but the IVF root is a public input of the circuit, which
+/// the verifier sets to the given `ivf_root` (e.g. the one published along with the index).
+///
+/// Returns the indices of the probed centroids, the index & value of the result, along with
+/// whether the constraints are satisfied.
+pub fn chip_ivf_query_multi_probe_with_root<const P: usize>(
+    query: &Vec<f64>,
+    centroids: &Vec<Vec<f64>>,
+    cluster_roots: &Vec<F>,
+    clusters: &[Vec<Vec<f64>>; P],
+    scheme: CommitmentScheme,
+    metric: &str,
+    ivf_root: F,
+) -> (Vec<usize>, usize, Vec<f64>, bool) {
+    let (builder, cluster_ids, index, result, root) =
+        ivf_query_multi_probe::<P>(query, centroids, cluster_roots, clusters, scheme, metric);
+
+    let satisfied =
+        common::is_satisfied_with_instances(builder, 16, LOOKUP_BITS, vec![root], vec![ivf_root]);
+
+    (cluster_ids, index, result, satisfied)
+}
+
+/// Builds the circuit of `chip_ivf_query_multi_probe` without running the mock prover.
+///
+/// Returns the builder along with the outputs of `chip_ivf_query_multi_probe`, where the IVF root
+/// is still assigned so that it can be made public.
+fn ivf_query_multi_probe<const P: usize>(
+    query: &Vec<f64>,
+    centroids: &Vec<Vec<f64>>,
+    cluster_roots: &Vec<F>,
+    clusters: &[Vec<Vec<f64>>; P],
+    scheme: CommitmentScheme,
+    metric: &str,
+) -> (GateThreadBuilder<F>, Vec<usize>, usize, Vec<f64>, AssignedValue<F>) {
+    let mut builder = GateThreadBuilder::mock();
+    let ctx = builder.main(0);
+    let fixed_point_chip = FixedPointChip::<F, PRECISION_BITS>::default(LOOKUP_BITS);
+    let distance_chip = DistanceChip::default(&fixed_point_chip);
+    let vectordb_chip = VectorDBChip::default(&fixed_point_chip);
+    let mut poseidon_chip = PARAMS.poseidon_chip(ctx);
+
+    let qquery: Vec<AssignedValue<F>> = fixed_point_chip.quantize_and_assign_vector(ctx, query);
+    let qcentroids: Vec<Vec<AssignedValue<F>>> =
+        quantize_vectors(centroids).into_iter().map(|v| ctx.assign_witnesses(v)).collect();
+    let qroots: Vec<AssignedValue<F>> = ctx.assign_witnesses(cluster_roots.clone());
+    let qclusters: [Vec<Vec<AssignedValue<F>>>; P] = core::array::from_fn(|i| {
+        quantize_vectors(&clusters[i]).into_iter().map(|v| ctx.assign_witnesses(v)).collect()
+    });
+
+    let ivf = vectordb_chip.ivf_query_multi_probe::<P, T, RATE>(
+        ctx,
+        &mut poseidon_chip,
+        scheme,
+        &qquery,
+        &qcentroids,
+        &qroots,
+        &qclusters,
+        &|ctx, a, b| match metric {
+            "euclidean" => distance_chip.euclidean_distance(ctx, a, b),
+            "manhattan" => distance_chip.manhattan_distance(ctx, a, b),
+            _ => panic!("unknown metric {}", metric),
+        },
+    );
+
+    let position = |indicator: &Vec<AssignedValue<F>>| {
+        indicator
+            .iter()
+            .position(|v| common::compare_fields::<F>(v.value(), &F::one()))
+            .expect("expected 1")
+    };
+    let cluster_ids: Vec<usize> = ivf.centroid_indicators.iter().map(position).collect();
+    let index = position(&ivf.indicator);
+    let result = fixed_point_chip.dequantize_vector(&ivf.result);
+
+    (builder, cluster_ids, index, result, ivf.ivf_root)
+}
+
+/// Finds the `K` nearest vectors among the first `len` of the given slots within the chip, where
+/// the rest are padding slots of a database with a fixed capacity, and commits to the database
+/// with the domain-separated scheme, which binds `len`.
+///
+/// Returns the index of each result & the root, along with whether the constraints are satisfied.
+pub fn chip_top_k_with_length<const K: usize>(
+    query: &Vec<f64>,
+    slots: &Vec<Vec<f64>>,
+    len: usize,
+) -> (Vec<usize>, F, bool) {
+    let mut builder = GateThreadBuilder::mock();
+    let ctx = builder.main(0);
+    let fixed_point_chip = FixedPointChip::<F, PRECISION_BITS>::default(LOOKUP_BITS);
+    let distance_chip = DistanceChip::default(&fixed_point_chip);
+    let vectordb_chip = VectorDBChip::default(&fixed_point_chip);
+    let mut poseidon_chip = PARAMS.poseidon_chip(ctx);
+
+    let qquery: Vec<AssignedValue<F>> = fixed_point_chip.quantize_and_assign_vector(ctx, query);
+    let qslots: Vec<Vec<AssignedValue<F>>> =
+        quantize_vectors(slots).into_iter().map(|v| ctx.assign_witnesses(v)).collect();
+    let len = ctx.load_witness(F::from(len as u64));
+
+    let no_metadata = vec![vec![]; slots.len()];
+    let results = vectordb_chip.top_k_with_length::<K>(
+        ctx,
+        &qquery,
+        &qslots,
+        &no_metadata,
+        len,
+        &|ctx, a, b| distance_chip.euclidean_distance(ctx, a, b),
+    );
+    let indices: Vec<usize> = results
+        .iter()
+        .map(|result| {
+            result
+                .indicator
+                .iter()
+                .position(|v| common::compare_fields::<F>(v.value(), &F::one()))
+                .expect("expected 1")
+        })
+        .collect();
+
+    let root = vectordb_chip.merkle_commitment_with_length(
+        ctx,
+        &mut poseidon_chip,
+        CommitmentScheme::DomainSeparated,
+        &qslots,
+        len,
+    );
+
+    let satisfied = common::is_satisfied(builder, 15, LOOKUP_BITS);
+
+    (indices, *root.value(), satisfied)
+}
+
+pub fn chip_nearest_vector(query: &Vec<f64>, vectors: &Vec<Vec<f64>>) -> (usize, Vec<f64>, F) {
+    let mut builder = GateThreadBuilder::mock();
+    let ctx = builder.main(0);
+    let fixed_point_chip = FixedPointChip::<F, PRECISION_BITS>::default(LOOKUP_BITS);
+    let distance_chip = DistanceChip::default(&fixed_point_chip);
+    let vectordb_chip = VectorDBChip::default(&fixed_point_chip);
+    let mut poseidon_chip = PARAMS.poseidon_chip(ctx);
+
+    let qquery: Vec<AssignedValue<F>> = fixed_point_chip.quantize_and_assign_vector(ctx, query);
+    let qvectors: Vec<Vec<AssignedValue<F>>> = vectors
+        .iter()
+        .map(|v| ctx.assign_witnesses(fixed_point_chip.quantize_vector(&v)))
+        .collect();
+
+    let (indicator, result) =
+        vectordb_chip.nearest_vector(ctx, &qquery, &qvectors, &|ctx, a, b| {
+            distance_chip.euclidean_distance(ctx, a, b)
+        });
+
+    let result = fixed_point_chip.dequantize_vector(&result);
+    let index = indicator
+        .iter()
+        .position(|v| common::compare_fields::<F>(v.value(), &F::one()))
+        .expect("expected 1");
+
+    let root = vectordb_chip.merkle_commitment::<T, RATE>(ctx, &mut poseidon_chip, &qvectors);
+
+    (index, result, *root.value())
+}
+
+pub fn chip_merkle(vectors: &Vec<Vec<f64>>) -> F {
+    let mut builder = GateThreadBuilder::mock();
+    let ctx = builder.main(0);
+    let fixed_point_chip = FixedPointChip::<F, PRECISION_BITS>::default(LOOKUP_BITS);
+    let vectordb_chip = VectorDBChip::default(&fixed_point_chip);
+    let mut poseidon_chip = PARAMS.poseidon_chip(ctx);
+
+    let qvectors: Vec<Vec<AssignedValue<F>>> = vectors
+        .iter()
+        .map(|v| ctx.assign_witnesses(fixed_point_chip.quantize_vector(&v)))
+        .collect();
+
+    let root = vectordb_chip.merkle_commitment(ctx, &mut poseidon_chip, &qvectors);
+    *root.value()
+}
+
+/// Computes the Merkle root over the given vectors natively, quantizing them the same
+/// way as the chip does.
+pub fn native_merkle(vectors: &Vec<Vec<f64>>) -> F {
+    let fixed_point_chip = FixedPointChip::<F, PRECISION_BITS>::default(LOOKUP_BITS);
+    let qvectors: Vec<Vec<F>> =
+        vectors.iter().map(|v| fixed_point_chip.quantize_vector(&v)).collect();
+
+    merkle::merkle_root(&qvectors)
+}
+
+/// Computes the Keccak Merkle root over the given (already quantized) vectors within the chip.
+///
+/// The Keccak computations are only constrained in the second phase of the `EthCircuitBuilder`,
+/// so the circuit is built the same way as `run_eth` does.
+///
+/// Returns the witnessed root, along with whether the constraints are satisfied.
+pub fn chip_keccak_merkle(vectors: &Vec<Vec<F>>) -> ([u8; 32], bool) {
+    // the fixed-point chip shares the range lookup table of the Keccak circuit
+    set_var("LOOKUP_BITS", LOOKUP_BITS.to_string());
+    let mut root = [0u8; 32];
+
+    let precircuit = EthScaffold::new(
+        |builder: &mut GateThreadBuilder<F>,
+         _: &EthChip<F>,
+         keccak: &mut KeccakChip<F>,
+         vectors: Vec<Vec<F>>,
+         _: &mut Vec<AssignedValue<F>>| {
+            let ctx = builder.main(0);
+            let fixed_point_chip = FixedPointChip::<F, PRECISION_BITS>::default(LOOKUP_BITS);
+            let vectordb_chip = VectorDBChip::default(&fixed_point_chip);
+
+            let qvectors: Vec<Vec<AssignedValue<F>>> =
+                vectors.into_iter().map(|v| ctx.assign_witnesses(v)).collect();
+
+            let assigned_root = vectordb_chip.keccak_merkle_commitment(ctx, keccak, &qvectors);
+            assigned_root
+                .iter()
+                .zip(root.iter_mut())
+                .for_each(|(a, b)| *b = a.value().get_lower_32() as u8);
+
+            |_: &mut Context<F>, _: &mut Context<F>, _: &EthChip<F>| {}
+        },
+        vectors.clone(),
+    );
+    let satisfied = common::is_precircuit_satisfied(precircuit, 15);
+    (root, satisfied)
+}
+
+/// Computes the Keccak Merkle root over the given (already quantized) vectors natively, the
+/// same way as a smart contract would with `abi.encodePacked` over the `int256` values.
+pub fn native_keccak_merkle(vectors: &Vec<Vec<F>>) -> [u8; 32] {
+    let mut leaves: Vec<[u8; 32]> = vectors
+        .iter()
+        .map(|v| {
+            let bytes: Vec<u8> = v
+                .iter()
+                .flat_map(|x| {
+                    // small values are non-negative, and the rest are negated
+                    let value = if F::from_u128(x.get_lower_128()) == *x {
+                        x.get_lower_128() as i128
+                    } else {
+                        -((-*x).get_lower_128() as i128)
+                    };
+                    let sign = if value < 0 { 0xff } else { 0 };
+                    [vec![sign; 16], value.to_be_bytes().to_vec()].concat()
+                })
+                .collect();
+            keccak256(bytes)
+        })
+        .collect();
+    leaves.resize(leaves.len().next_power_of_two(), [0; 32]);
+
+    while leaves.len() > 1 {
+        leaves = leaves.chunks(2).map(|children| keccak256(children.concat())).collect();
+    }
+    leaves[0]
+}
+
+/// Builds a native Merkle tree over the database, and verifies the authentication path of the
+/// vector at `index` within the chip, claiming that it is at `claimed_index` instead.
+///
+/// Returns the native Merkle root, along with whether the constraints are satisfied.
+pub fn chip_verify_merkle_path(
+    vectors: &Vec<Vec<f64>>,
+    index: usize,
+    claimed_index: usize,
+) -> (F, bool) {
+    let mut builder = GateThreadBuilder::mock();
+    let ctx = builder.main(0);
+    let fixed_point_chip = FixedPointChip::<F, PRECISION_BITS>::default(LOOKUP_BITS);
+    let vectordb_chip = VectorDBChip::default(&fixed_point_chip);
+    let mut poseidon_chip = PARAMS.poseidon_chip(ctx);
+
+    let qvectors: Vec<Vec<F>> =
+        vectors.iter().map(|v| fixed_point_chip.quantize_vector(&v)).collect();
+    let tree = MerkleTree::new(&qvectors, &PARAMS);
+    let path = tree.path(index);
+
+    let root = ctx.load_witness(tree.root());
+    let vector = ctx.assign_witnesses(qvectors[index].clone());
+    let claimed_index = ctx.load_witness(F::from(claimed_index as u64));
+    let siblings = ctx.assign_witnesses(path.siblings);
+    vectordb_chip.verify_merkle_path(
+        ctx,
+        &mut poseidon_chip,
+        root,
+        &vector,
+        claimed_index,
+        &siblings,
+    );
+
+    let satisfied = common::is_satisfied(builder, 14, LOOKUP_BITS);
+
+    (tree.root(), satisfied)
+}
+
+/// Commits to the given (already quantized) vectors within the chip, with respect to the
+/// commitment `scheme`.
+pub fn chip_merkle_with_scheme(vectors: &Vec<Vec<F>>, scheme: CommitmentScheme) -> F {
+    let mut builder = GateThreadBuilder::mock();
+    let ctx = builder.main(0);
+    let fixed_point_chip = FixedPointChip::<F, PRECISION_BITS>::default(LOOKUP_BITS);
+    let vectordb_chip = VectorDBChip::default(&fixed_point_chip);
+    let mut poseidon_chip = PARAMS.poseidon_chip(ctx);
+
+    let vectors: Vec<Vec<AssignedValue<F>>> =
+        vectors.iter().map(|v| ctx.assign_witnesses(v.clone())).collect();
+
+    let root =
+        vectordb_chip.merkle_commitment_with_scheme(ctx, &mut poseidon_chip, scheme, &vectors);
+    *root.value()
+}
+
+/// Builds a native Merkle tree over the database with respect to the commitment `scheme`, and
+/// verifies the authentication path of the vector at `index` within the chip, claiming that the
+/// database has `claimed_len` vectors.
+///
+/// Returns whether the constraints are satisfied.
+pub fn chip_verify_merkle_path_with_scheme(
+    vectors: &Vec<Vec<f64>>,
+    scheme: CommitmentScheme,
+    index: usize,
+    claimed_len: usize,
+) -> bool {
+    let mut builder = GateThreadBuilder::mock();
+    let ctx = builder.main(0);
+    let fixed_point_chip = FixedPointChip::<F, PRECISION_BITS>::default(LOOKUP_BITS);
+    let vectordb_chip = VectorDBChip::default(&fixed_point_chip);
+    let mut poseidon_chip = PARAMS.poseidon_chip(ctx);
+
+    let qvectors = quantize_vectors(vectors);
+    let tree = native_merkle_tree(&qvectors, scheme);
+    let path = tree.path(index);
+
+    let root = ctx.load_witness(tree.root());
+    let len = ctx.load_witness(F::from(claimed_len as u64));
+    let vector = ctx.assign_witnesses(qvectors[index].clone());
+    let index = ctx.load_witness(F::from(index as u64));
+    let siblings = ctx.assign_witnesses(path.siblings);
+    vectordb_chip.verify_merkle_path_with_scheme(
+        ctx,
+        &mut poseidon_chip,
+        scheme,
+        root,
+        len,
+        &vector,
+        index,
+        &siblings,
+    );
+
+    common::is_satisfied(builder, 14, LOOKUP_BITS)
+}
+
+/// Quantizes the given vectors the same way as the chip does.
+pub fn quantize_vectors(vectors: &Vec<Vec<f64>>) -> Vec<Vec<F>> {
+    let fixed_point_chip = FixedPointChip::<F, PRECISION_BITS>::default(LOOKUP_BITS);
+    vectors.iter().map(|v| fixed_point_chip.quantize_vector(&v)).collect()
+}
+
+/// Builds a native Merkle tree over the given (already quantized) vectors, with respect to
+/// the commitment `scheme`.
+pub fn native_merkle_tree(
+    vectors: &Vec<Vec<F>>,
+    scheme: CommitmentScheme,
+) -> MerkleTree<F, T, RATE> {
+    MerkleTree::new(vectors, &CommitmentParams { scheme, ..PARAMS })
+}
+
+/// Same as `native_merkle_tree`, but with room for `capacity` vectors.
+pub fn native_merkle_tree_with_capacity(
+    vectors: &Vec<Vec<F>>,
+    capacity: usize,
+    scheme: CommitmentScheme,
+) -> MerkleTree<F, T, RATE> {
+    MerkleTree::new_with_capacity(vectors, capacity, &CommitmentParams { scheme, ..PARAMS })
+}
+
+/// Commits to the database with the domain-separated scheme and room for `capacity` vectors,
+/// and applies the given updates (kind, index & new vector) one after another, both natively
+/// and within the chip, where each update starts from the root of the previous one.
+///
+/// Returns the final native root & the final chip root, along with whether the constraints are satisfied.
+pub fn chip_merkle_updates(
+    vectors: &Vec<Vec<f64>>,
+    capacity: usize,
+    updates: &Vec<(MerkleUpdate, usize, Vec<f64>)>,
+) -> (F, F, bool) {
+    let mut builder = GateThreadBuilder::mock();
+    let ctx = builder.main(0);
+    let fixed_point_chip = FixedPointChip::<F, PRECISION_BITS>::default(LOOKUP_BITS);
+    let vectordb_chip = VectorDBChip::default(&fixed_point_chip);
+    let mut poseidon_chip = PARAMS.poseidon_chip(ctx);
+    let scheme = CommitmentScheme::DomainSeparated;
+
+    let mut qvectors = quantize_vectors(vectors);
+    let mut tree = native_merkle_tree_with_capacity(&qvectors, capacity, scheme);
+
+    let mut root = ctx.load_witness(tree.root());
+    let mut len = ctx.load_witness(F::from(qvectors.len() as u64));
+    for (update, index, vector) in updates {
+        let new_vector = fixed_point_chip.quantize_vector(vector);
+        let old_vector =
+            qvectors.get(*index).cloned().unwrap_or_else(|| vec![F::zero(); new_vector.len()]);
+        let path = tree.path(*index);
+
+        // update natively
+        match update {
+            MerkleUpdate::Insert => {
+                tree.insert(&new_vector);
+                qvectors.push(new_vector.clone());
+            }
+            MerkleUpdate::Replace => {
+                tree.replace(*index, &new_vector);
+                qvectors[*index] = new_vector.clone();
+            }
+            MerkleUpdate::Delete => tree.delete(*index),
+        }
+
+        // update within the chip
+        let index = ctx.load_witness(F::from(*index as u64));
+        let siblings = ctx.assign_witnesses(path.siblings);
+        let old_vector = ctx.assign_witnesses(old_vector);
+        let new_vector = ctx.assign_witnesses(new_vector);
+        (root, len) = vectordb_chip.merkle_update(
+            ctx,
+            &mut poseidon_chip,
+            scheme,
+            *update,
+            root,
+            len,
+            index,
+            &siblings,
+            &old_vector,
+            &new_vector,
+        );
+    }
+    let chip_root = *root.value();
+
+    let satisfied = common::is_satisfied(builder, 14, LOOKUP_BITS);
+
+    (tree.root(), chip_root, satisfied)
+}
+
+/// Appends `vectors` to an append-only tree of the given `depth`, and then appends `batch` both
+/// natively and within the chip, with the domain-separated scheme. If `tamper` is set, the
+/// frontier given to the chip is modified at its first level.
+///
+/// Returns the native root & the chip root after the batch, along with whether the constraints are satisfied.
+pub fn chip_merkle_append(
+    vectors: &Vec<Vec<f64>>,
+    batch: &Vec<Vec<f64>>,
+    depth: usize,
+    tamper: bool,
+) -> (F, F, bool) {
+    let mut builder = GateThreadBuilder::mock();
+    let ctx = builder.main(0);
+    let fixed_point_chip = FixedPointChip::<F, PRECISION_BITS>::default(LOOKUP_BITS);
+    let vectordb_chip = VectorDBChip::default(&fixed_point_chip);
+    let mut poseidon_chip = PARAMS.poseidon_chip(ctx);
+    let scheme = CommitmentScheme::DomainSeparated;
+
+    let mut tree =
+        IncrementalMerkleTree::new(depth, batch[0].len(), &CommitmentParams { scheme, ..PARAMS });
+    for vector in quantize_vectors(vectors) {
+        tree.append(&vector);
+    }
+
+    let old_root = ctx.load_witness(tree.root());
+    let len = ctx.load_witness(F::from(tree.num_vectors() as u64));
+    let mut frontier = tree.frontier().clone();
+    if tamper {
+        frontier[0] += F::one();
+    }
+    let frontier = ctx.assign_witnesses(frontier);
+
+    let qbatch = quantize_vectors(batch);
+    for vector in qbatch.iter() {
+        tree.append(vector);
+    }
+
+    let qbatch: Vec<Vec<AssignedValue<F>>> =
+        qbatch.into_iter().map(|v| ctx.assign_witnesses(v)).collect();
+    let (new_root, _, _) = vectordb_chip.merkle_append(
+        ctx,
+        &mut poseidon_chip,
+        scheme,
+        old_root,
+        len,
+        &frontier,
+        &qbatch,
+    );
+    let chip_root = *new_root.value();
+
+    let satisfied = common::is_satisfied(builder, 14, LOOKUP_BITS);
+
+    (tree.root(), chip_root, satisfied)
+}
+
+/// Commits to the given entries (id, vector & metadata) with a sparse Merkle tree of the given
+/// `depth`, and finds the nearest entry to the `query` within the chip, where each entry is
+/// verified to be in the tree. It also verifies that there is no entry with `absent_id`.
+///
+/// Returns the id of the nearest entry, along with whether the constraints are satisfied.
+pub fn chip_sparse_nearest_vector(
+    entries: &Vec<(u64, Vec<f64>, Vec<u64>)>,
+    query: &Vec<f64>,
+    depth: usize,
+    absent_id: u64,
+) -> (u64, bool) {
+    let mut builder = GateThreadBuilder::mock();
+    let ctx = builder.main(0);
+    let fixed_point_chip = FixedPointChip::<F, PRECISION_BITS>::default(LOOKUP_BITS);
+    let distance_chip = DistanceChip::default(&fixed_point_chip);
+    let vectordb_chip = VectorDBChip::default(&fixed_point_chip);
+    let mut poseidon_chip = PARAMS.poseidon_chip(ctx);
+    let scheme = CommitmentScheme::DomainSeparated;
+
+    let mut tree = SparseMerkleTree::new(depth, &CommitmentParams { scheme, ..PARAMS });
+    let entries: Vec<(u64, Vec<F>, Vec<F>)> = entries
+        .iter()
+        .map(|(id, vector, metadata)| {
+            (
+                *id,
+                fixed_point_chip.quantize_vector(vector),
+                metadata.iter().map(|m| F::from(*m)).collect(),
+            )
+        })
+        .collect();
+    for (id, vector, metadata) in entries.iter() {
+        tree.insert(*id, vector, metadata);
+    }
+    let root = ctx.load_witness(tree.root());
+
+    // open each entry from the tree
+    let mut ids: Vec<AssignedValue<F>> = vec![];
+    let mut qvectors: Vec<Vec<AssignedValue<F>>> = vec![];
+    for (id, vector, metadata) in entries.iter() {
+        let siblings = ctx.assign_witnesses(tree.path(*id).siblings);
+        let id = ctx.load_witness(F::from(*id));
+        let vector = ctx.assign_witnesses(vector.clone());
+        let metadata = ctx.assign_witnesses(metadata.clone());
+        vectordb_chip.verify_sparse_inclusion(
+            ctx,
+            &mut poseidon_chip,
+            scheme,
+            root,
+            id,
+            &vector,
+            &metadata,
+            &siblings,
+        );
+        ids.push(id);
+        qvectors.push(vector);
+    }
+
+    let siblings = ctx.assign_witnesses(tree.path(absent_id).siblings);
+    let absent_id = ctx.load_witness(F::from(absent_id));
+    vectordb_chip.verify_sparse_non_inclusion(
+        ctx,
+        &mut poseidon_chip,
+        scheme,
+        root,
+        absent_id,
+        &siblings,
+    );
+
+    // the result id is selected with the same indicator as the nearest vector
+    let qquery = fixed_point_chip.quantize_and_assign_vector(ctx, query);
+    let (indicator, _) = vectordb_chip.nearest_vector(ctx, &qquery, &qvectors, &|ctx, a, b| {
+        distance_chip.euclidean_distance(ctx, a, b)
+    });
+    let result_id = fixed_point_chip.gate().select_by_indicator(ctx, ids, indicator);
+    let result_id = result_id.value().get_lower_128() as u64;
+
+    let satisfied = common::is_satisfied(builder, 14, LOOKUP_BITS);
+
+    (result_id, satisfied)
+}
+
+/// Builds a native Merkle tree with arity `ARITY` (and Poseidon width `WIDTH`) over the database
+/// using the commitment parameters `params`,
+/// and verifies the authentication path of the vector at `index` within the chip, claiming
+/// that it is at `claimed_index` instead. The chip also commits to the entire database.
+///
+/// Returns the native root & the chip root, along with whether the constraints are satisfied.
+pub fn chip_merkle_with_arity<const WIDTH: usize, const ARITY: usize>(
+    vectors: &Vec<Vec<f64>>,
+    params: &CommitmentParams<WIDTH, ARITY>,
+    index: usize,
+    claimed_index: usize,
+) -> (F, F, bool) {
+    let mut builder = GateThreadBuilder::mock();
+    let ctx = builder.main(0);
+    let fixed_point_chip = FixedPointChip::<F, PRECISION_BITS>::default(LOOKUP_BITS);
+    let vectordb_chip = VectorDBChip::default(&fixed_point_chip);
+    let mut poseidon_chip = params.poseidon_chip(ctx);
+    let scheme = params.scheme;
+
+    let qvectors = quantize_vectors(vectors);
+    let tree = MerkleTree::new(&qvectors, params);
+    let path = tree.path(index);
+
+    let assigned_vectors: Vec<Vec<AssignedValue<F>>> =
+        qvectors.iter().map(|v| ctx.assign_witnesses(v.clone())).collect();
+    let chip_root = vectordb_chip.merkle_commitment_with_scheme(
+        ctx,
+        &mut poseidon_chip,
+        scheme,
+        &assigned_vectors,
+    );
+
+    let len = ctx.load_witness(F::from(qvectors.len() as u64));
+    let claimed_index = ctx.load_witness(F::from(claimed_index as u64));
+    let siblings = ctx.assign_witnesses(path.siblings);
+    vectordb_chip.verify_merkle_path_with_scheme(
+        ctx,
+        &mut poseidon_chip,
+        scheme,
+        chip_root,
+        len,
+        &assigned_vectors[index],
+        claimed_index,
+        &siblings,
+    );
+    let chip_root = *chip_root.value();
+
+    let satisfied = common::is_satisfied(builder, 14, LOOKUP_BITS);
+
+    (tree.root(), chip_root, satisfied)
 }
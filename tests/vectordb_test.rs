@@ -1,5 +1,6 @@
 #[macro_use]
 extern crate assert_float_eq;
+use assert_float_eq::afe_is_relative_eq;
 
 mod common;
 mod distances;
@@ -8,6 +9,11 @@ mod vectordb;
 #[cfg(test)]
 mod test {
     use super::*;
+    use halo2_base::halo2_proofs::halo2curves::bn256::Fr as F;
+    use halo2_scaffold::gadget::vectordb::{
+        CommitmentParams, CommitmentScheme, Comparison, MerkleUpdate,
+    };
+    use halo2_scaffold::native::query::{commit_query, hash_result};
 
     #[test]
     fn test_kmeans_small() {
@@ -28,6 +34,967 @@ mod test {
         assert_eq!(clusterids_native, clusterids_chip);
     }
 
+    #[test]
+    fn test_kmeans_with_length() {
+        const K: usize = 2;
+        const I: usize = 2;
+        const DIM: usize = 3;
+        const CAPACITY: usize = 8;
+        let vectors = common::random_vectors(DIM, 6);
+
+        // padding slots far away from the vectors would pull the centroids if they were included
+        let mut slots = vectors.clone();
+        slots.resize(CAPACITY, vec![100.0; DIM]);
+
+        let (centroids_native, clusterids_native) =
+            vectordb::kmeans::<K, I>(&vectors, &distances::euclidean_distance);
+        let (centroids_chip, clusterids_chip, satisfied) =
+            vectordb::chip_kmeans_with_length::<K, I>(&slots, vectors.len());
+        assert!(satisfied, "expected constraints to be satisfied");
+        common::assert_multiple_vectors_eq(&centroids_native.to_vec(), &centroids_chip.to_vec());
+        assert_eq!(clusterids_native, clusterids_chip[..vectors.len()]);
+    }
+
+    #[test]
+    fn test_kmeans_empty_cluster() {
+        const K: usize = 2;
+        const I: usize = 2;
+        const DIM: usize = 3;
+
+        // duplicate initial centroids, so that every vector goes to the first cluster
+        // and the second one is empty after the first iteration
+        let mut vectors = vec![vec![1.0; DIM], vec![1.0; DIM]];
+        vectors.extend(common::random_vectors(DIM, 4));
+
+        // the empty cluster keeps its previous centroid instead of dividing by zero
+        let (centroids, _) = vectordb::kmeans_iteration::<K>(
+            &vectors,
+            &[vectors[0].clone(), vectors[1].clone()],
+            &distances::euclidean_distance,
+        );
+        assert_eq!(centroids[1], vectors[1]);
+
+        let (centroids_native, clusterids_native) =
+            vectordb::kmeans::<K, I>(&vectors, &distances::euclidean_distance);
+        let (centroids_chip, clusterids_chip) = vectordb::chip_kmeans::<K, I>(&vectors);
+        common::assert_multiple_vectors_eq(&centroids_native.to_vec(), &centroids_chip.to_vec());
+        assert_eq!(clusterids_native, clusterids_chip);
+        assert!(vectordb::chip_kmeans_is_satisfied::<K, I>(&vectors));
+    }
+
+    #[test]
+    fn test_kmeans_selectors() {
+        const K: usize = 3;
+        const I: usize = 2;
+        const DIM: usize = 4;
+        let vectors = common::random_vectors(DIM, 12);
+
+        let (_, clusterids_native) =
+            vectordb::kmeans::<K, I>(&vectors, &distances::euclidean_distance);
+        let (selectors_chip, clusterids_chip) = vectordb::chip_kmeans_selectors::<K, I>(&vectors);
+
+        // selectors must be one-hot with a field one at the cluster id
+        for ((selectors, id_chip), id_native) in
+            selectors_chip.iter().zip(clusterids_chip).zip(clusterids_native)
+        {
+            assert_eq!(id_chip, F::from(id_native as u64));
+            for (k, s) in selectors.iter().enumerate() {
+                assert_eq!(*s, if k == id_native { F::one() } else { F::zero() });
+            }
+        }
+    }
+
+    #[test]
+    fn test_spherical_kmeans() {
+        const K: usize = 2;
+        const I: usize = 3;
+        const DIM: usize = 5;
+        let vectors: Vec<Vec<f64>> =
+            common::random_vectors(DIM, 20).iter().map(|v| vectordb::normalize(v)).collect();
+
+        let (centroids_native, clusterids_native) =
+            vectordb::spherical_kmeans::<K, I>(&vectors, &distances::cosine_distance);
+        let (centroids_chip, clusterids_chip) = vectordb::chip_spherical_kmeans::<K, I>(&vectors);
+        common::assert_multiple_vectors_eq(&centroids_native.to_vec(), &centroids_chip.to_vec());
+        assert_eq!(clusterids_native, clusterids_chip);
+    }
+
+    #[test]
+    fn test_kmedoids() {
+        const K: usize = 3;
+        const I: usize = 3;
+        const DIM: usize = 4;
+        let vectors = common::random_vectors(DIM, 12);
+
+        // use a non-Euclidean metric, where taking the mean would make no sense
+        let (medoids_native, clusterids_native) =
+            vectordb::kmedoids::<K, I>(&vectors, &distances::manhattan_distance);
+        let (medoids_chip, clusterids_chip, medoid_vectors_chip, satisfied) =
+            vectordb::chip_kmedoids::<K, I>(&vectors);
+        assert!(satisfied, "expected constraints to be satisfied");
+        assert_eq!(medoids_native, medoids_chip);
+        assert_eq!(clusterids_native, clusterids_chip);
+
+        // medoids are members of the database
+        let medoid_vectors_native: Vec<Vec<f64>> =
+            medoids_native.iter().map(|m| vectors[*m].clone()).collect();
+        common::assert_multiple_vectors_eq(&medoid_vectors_native, &medoid_vectors_chip.to_vec());
+    }
+
+    #[test]
+    fn test_kmedoids_empty_cluster() {
+        const K: usize = 3;
+        const I: usize = 2;
+        const DIM: usize = 4;
+
+        // duplicate initial medoids, so that the second cluster is empty as its members
+        // go to the first medoid, and it keeps its medoid instead of picking vector 0
+        let vectors: Vec<Vec<f64>> =
+            [0.25, 0.25, 0.75, 0.25, 0.75, 0.25, 0.75].iter().map(|x| vec![*x; DIM]).collect();
+
+        let (medoids_native, clusterids_native) =
+            vectordb::kmedoids::<K, I>(&vectors, &distances::manhattan_distance);
+        assert_eq!(medoids_native[1], 1);
+        assert!(!clusterids_native.contains(&1));
+
+        let (medoids_chip, clusterids_chip, _, satisfied) =
+            vectordb::chip_kmedoids::<K, I>(&vectors);
+        assert!(satisfied, "expected constraints to be satisfied");
+        assert_eq!(medoids_native, medoids_chip);
+        assert_eq!(clusterids_native, clusterids_chip);
+    }
+
+    #[test]
+    fn test_kmedoids_large_cost() {
+        const K: usize = 3;
+        const I: usize = 2;
+        const DIM: usize = 4;
+
+        // the far vectors join the cluster of a near medoid, so that its total distance
+        // reaches the sentinel and it could tie with the masked non-members
+        let mut vectors = common::random_vectors(DIM, 6);
+        vectors.extend(
+            common::random_vectors(DIM, 6)
+                .into_iter()
+                .map(|v| v.into_iter().map(|x| x + 2f64.powi(45)).collect()),
+        );
+
+        let (_, _, _, satisfied) = vectordb::chip_kmedoids::<K, I>(&vectors);
+        assert!(!satisfied, "expected constraints to fail for a cost beyond the sentinel");
+    }
+
+    #[test]
+    fn test_hierarchical_kmeans() {
+        const B: usize = 2;
+        const I: usize = 2;
+        const DIM: usize = 4;
+        let depth = 2;
+        let vectors = common::random_vectors(DIM, 16);
+
+        let (levels_native, clusterids_native) =
+            vectordb::hierarchical_kmeans::<B, I>(&vectors, depth, &distances::euclidean_distance);
+
+        for scheme in [CommitmentScheme::Plain, CommitmentScheme::DomainSeparated] {
+            let (levels_chip, clusterids_chip, roots_match, satisfied) =
+                vectordb::chip_hierarchical_kmeans::<B, I>(&vectors, depth, "euclidean", scheme);
+            assert!(satisfied, "expected constraints to be satisfied");
+            assert!(roots_match, "native & chip level roots differ for {:?}", scheme);
+
+            assert_eq!(levels_native.len(), depth);
+            for (l, (centroids_native, centroids_chip)) in
+                levels_native.iter().zip(levels_chip.iter()).enumerate()
+            {
+                assert_eq!(centroids_native.len(), B.pow(l as u32 + 1));
+                common::assert_multiple_vectors_eq(centroids_native, centroids_chip);
+            }
+            assert_eq!(clusterids_native, clusterids_chip);
+        }
+    }
+
+    #[test]
+    fn test_hierarchical_kmeans_small_clusters() {
+        const B: usize = 3;
+        const I: usize = 2;
+        const DIM: usize = 3;
+        let depth = 2;
+
+        // with only four unit vectors, some of the sub-clusters have less than B members,
+        // whose initial centroids must not be zero vectors for the cosine distance
+        let vectors: Vec<Vec<f64>> =
+            common::random_vectors(DIM, 4).iter().map(|v| vectordb::normalize(v)).collect();
+
+        let (levels_native, clusterids_native) =
+            vectordb::hierarchical_kmeans::<B, I>(&vectors, depth, &distances::cosine_distance);
+        let (levels_chip, clusterids_chip, roots_match, satisfied) =
+            vectordb::chip_hierarchical_kmeans::<B, I>(
+                &vectors,
+                depth,
+                "cosine",
+                CommitmentScheme::DomainSeparated,
+            );
+        assert!(satisfied, "expected constraints to be satisfied");
+        assert!(roots_match, "native & chip level roots differ");
+        for (centroids_native, centroids_chip) in levels_native.iter().zip(levels_chip.iter()) {
+            assert!(centroids_native.iter().all(|c| c.iter().any(|x| *x != 0.0)));
+            common::assert_multiple_vectors_eq(centroids_native, centroids_chip);
+        }
+        assert_eq!(clusterids_native, clusterids_chip);
+    }
+
+    #[test]
+    fn test_kmeans_convergence() {
+        const K: usize = 2;
+        const I: usize = 2;
+        const DIM: usize = 5;
+        let vectors = common::random_vectors(DIM, 20);
+
+        let (centroids_native, _, objective_native, movement_native) =
+            vectordb::kmeans_with_convergence::<K, I>(&vectors, &distances::euclidean_distance);
+        let (centroids_chip, objective_chip, movement_chip) =
+            vectordb::chip_kmeans_with_convergence::<K, I>(&vectors);
+        common::assert_multiple_vectors_eq(&centroids_native.to_vec(), &centroids_chip.to_vec());
+        assert_float_relative_eq!(objective_native, objective_chip);
+        assert_float_relative_eq!(movement_native, movement_chip);
+    }
+
+    #[test]
+    fn test_kmeans_objective() {
+        const K: usize = 2;
+        const I: usize = 2;
+        const DIM: usize = 8;
+        let vectors = common::random_vectors(DIM, 10);
+
+        let (centroids, cluster_ids, objective_native, _) =
+            vectordb::kmeans_with_convergence::<K, I>(&vectors, &distances::euclidean_distance);
+        let (objective_chip, satisfied) =
+            vectordb::chip_kmeans_objective::<K>(&vectors, &centroids, &cluster_ids);
+        assert!(satisfied, "expected constraints to be satisfied");
+        assert_float_relative_eq!(objective_native, objective_chip);
+
+        // each squared distance is 2^47, so their sum overflows the fixed-point range
+        let vectors = vec![vec![2f64.powi(22); DIM]; 8];
+        let centroids = [vec![0.0; DIM], vec![0.0; DIM]];
+        let (_, satisfied) =
+            vectordb::chip_kmeans_objective::<K>(&vectors, &centroids, &vec![0; 8]);
+        assert!(!satisfied, "expected constraints to fail for an out-of-range objective");
+    }
+
+    #[test]
+    fn test_kmeans_with_roots() {
+        const K: usize = 2;
+        const I: usize = 2;
+        const DIM: usize = 3;
+        let vectors = common::random_vectors(DIM, 8);
+
+        let (centroids_native, clusterids_native) =
+            vectordb::kmeans::<K, I>(&vectors, &distances::euclidean_distance);
+
+        for scheme in [CommitmentScheme::Plain, CommitmentScheme::DomainSeparated] {
+            let (centroids_chip, clusterids_chip, _, _, cluster_roots, satisfied) =
+                vectordb::chip_kmeans_with_roots::<K, I>(&vectors, false, scheme);
+            common::assert_multiple_vectors_eq(
+                &centroids_native.to_vec(),
+                &centroids_chip.to_vec(),
+            );
+            assert_eq!(clusterids_native, clusterids_chip);
+            assert!(satisfied, "expected constraints to be satisfied");
+
+            // each cluster root is the same as the native one under the given scheme
+            assert_eq!(cluster_roots.len(), K);
+            for (cluster_id, root) in cluster_roots.iter().enumerate() {
+                let cluster = common::select_cluster(&vectors, &clusterids_native, cluster_id);
+                let tree =
+                    vectordb::native_merkle_tree(&vectordb::quantize_vectors(&cluster), scheme);
+                assert_eq!(tree.root(), *root, "cluster roots differ for {:?}", scheme);
+            }
+
+            // another set of vectors with the same mean should not pass as the cluster
+            let (_, _, _, _, _, satisfied) =
+                vectordb::chip_kmeans_with_roots::<K, I>(&vectors, true, scheme);
+            assert!(!satisfied, "expected constraints to fail for a tampered cluster");
+        }
+    }
+
+    #[test]
+    fn test_cluster_membership() {
+        const DIM: usize = 3;
+        let vectors = vec![common::random_vector(DIM), vec![0.0; DIM]];
+        let cluster = vec![vectors[0].clone()];
+
+        let selectors = vec![F::from(1), F::from(0)];
+        assert!(vectordb::chip_cluster_membership(&vectors, &selectors, &cluster));
+
+        // non-boolean selectors with the right count, which would otherwise pass as the
+        // zero vector is unaffected by the scaling of the second selector
+        let selectors = vec![F::from(2), -F::from(1)];
+        assert!(!vectordb::chip_cluster_membership(&vectors, &selectors, &cluster));
+    }
+
+    #[test]
+    fn test_index() {
+        const K: usize = 2;
+        const I: usize = 2;
+        const DIM: usize = 3;
+        let vectors = common::random_vectors(DIM, 8);
+
+        let (centroids, cluster_ids, database_root, centroids_root, cluster_roots, satisfied) =
+            vectordb::chip_index::<K, I>(&vectors, CommitmentScheme::Plain);
+        assert!(satisfied, "expected constraints to be satisfied");
+
+        // roots should match the ones computed separately
+        assert_eq!(database_root, vectordb::chip_merkle(&vectors));
+        assert_eq!(centroids_root, vectordb::chip_merkle(&centroids.to_vec()));
+        for (cluster_id, cluster_root) in cluster_roots.iter().enumerate() {
+            let cluster = common::select_cluster(&vectors, &cluster_ids, cluster_id);
+            if !cluster.is_empty() {
+                assert_eq!(*cluster_root, vectordb::chip_merkle(&cluster));
+            }
+        }
+    }
+
+    #[test]
+    fn test_ivf_query() {
+        const K: usize = 2;
+        const I: usize = 2;
+        const DIM: usize = 3;
+        let vectors = common::random_vectors(DIM, 8);
+        let query = common::random_vector(DIM);
+
+        // the cluster roots of the index are recomputed by the query with the same scheme
+        for scheme in [CommitmentScheme::Plain, CommitmentScheme::DomainSeparated] {
+            let (centroids, cluster_ids, _, _, cluster_roots, satisfied) =
+                vectordb::chip_index::<K, I>(&vectors, scheme);
+            assert!(satisfied, "expected constraints to be satisfied");
+            let centroids = centroids.to_vec();
+            let ivf_root = vectordb::native_ivf_root(&centroids, &cluster_roots, scheme);
+
+            // two-level search natively
+            let (cluster_id, _) =
+                vectordb::nearest_vector(&query, &centroids, &distances::euclidean_distance);
+            let cluster = common::select_cluster(&vectors, &cluster_ids, cluster_id);
+            let (idx_native, result_native) =
+                vectordb::nearest_vector(&query, &cluster, &distances::euclidean_distance);
+
+            let (cluster_id_chip, idx_chip, result_chip, root, satisfied) =
+                vectordb::chip_ivf_query(&query, &centroids, &cluster_roots, &cluster, scheme);
+            assert!(satisfied, "expected constraints to be satisfied");
+            assert_eq!(cluster_id, cluster_id_chip);
+            assert_eq!(idx_native, idx_chip);
+            common::assert_vectors_eq(&result_native, &result_chip);
+            assert_eq!(root, ivf_root);
+
+            // opening another cluster should fail
+            let other = common::select_cluster(&vectors, &cluster_ids, 1 - cluster_id);
+            if !other.is_empty() {
+                let (_, _, _, _, satisfied) =
+                    vectordb::chip_ivf_query(&query, &centroids, &cluster_roots, &other, scheme);
+                assert!(!satisfied, "expected constraints to fail for another cluster");
+            }
+        }
+    }
+
+    #[test]
+    fn test_ivf_query_multi_probe() {
+        const K: usize = 4;
+        const I: usize = 2;
+        const P: usize = 2;
+        const DIM: usize = 3;
+        let vectors = common::random_vectors(DIM, 12);
+        let query = common::random_vector(DIM);
+
+        let scheme = CommitmentScheme::DomainSeparated;
+        let (centroids, cluster_ids, _, _, cluster_roots, satisfied) =
+            vectordb::chip_index::<K, I>(&vectors, scheme);
+        assert!(satisfied, "expected constraints to be satisfied");
+        let centroids = centroids.to_vec();
+        let ivf_root = vectordb::native_ivf_root(&centroids, &cluster_roots, scheme);
+
+        // multi-probe search natively, over the union of the probed clusters
+        let probes = vectordb::top_k(&query, &centroids, P, &distances::euclidean_distance);
+        let clusters: [Vec<Vec<f64>>; P] =
+            core::array::from_fn(|i| common::select_cluster(&vectors, &cluster_ids, probes[i]));
+        let union: Vec<Vec<f64>> = clusters.iter().flatten().cloned().collect();
+        if union.is_empty() {
+            return;
+        }
+        let (idx_native, result_native) =
+            vectordb::nearest_vector(&query, &union, &distances::euclidean_distance);
+
+        let (probes_chip, idx_chip, result_chip, root, satisfied) =
+            vectordb::chip_ivf_query_multi_probe::<P>(
+                &query,
+                &centroids,
+                &cluster_roots,
+                &clusters,
+                scheme,
+                "euclidean",
+            );
+        assert!(satisfied, "expected constraints to be satisfied");
+        assert_eq!(probes, probes_chip);
+        assert!(probes_chip.iter().all(|id| probes_chip.iter().filter(|p| *p == id).count() == 1));
+        assert_eq!(idx_native, idx_chip);
+        common::assert_vectors_eq(&result_native, &result_chip);
+        assert_eq!(root, ivf_root);
+
+        // with the IVF root as a public input, only the root of the index is accepted
+        for (expected_root, expected) in [(ivf_root, true), (ivf_root + F::one(), false)] {
+            let (_, _, _, satisfied) = vectordb::chip_ivf_query_multi_probe_with_root::<P>(
+                &query,
+                &centroids,
+                &cluster_roots,
+                &clusters,
+                scheme,
+                "euclidean",
+                expected_root,
+            );
+            assert_eq!(satisfied, expected, "expected public IVF root to be checked");
+        }
+
+        // probing a farther cluster instead of a nearer one should fail
+        let farthest =
+            vectordb::top_k(&query, &centroids, K, &distances::euclidean_distance)[K - 1];
+        let other = common::select_cluster(&vectors, &cluster_ids, farthest);
+        if cluster_roots[farthest] != cluster_roots[probes[P - 1]] {
+            let (_, _, _, _, satisfied) = vectordb::chip_ivf_query_multi_probe::<P>(
+                &query,
+                &centroids,
+                &cluster_roots,
+                &[clusters[0].clone(), other],
+                scheme,
+                "euclidean",
+            );
+            assert!(!satisfied, "expected constraints to fail for a farther cluster");
+        }
+
+        // a centroid distance beyond the sentinel could tie with the masked probes, so that the
+        // same cluster is probed twice; it is rejected even though it is not probed
+        let ranked = vectordb::top_k(&query, &centroids, K, &distances::manhattan_distance);
+        let clusters: [Vec<Vec<f64>>; P] =
+            core::array::from_fn(|i| common::select_cluster(&vectors, &cluster_ids, ranked[i]));
+        if clusters.iter().all(|cluster| cluster.is_empty()) {
+            return;
+        }
+        let mut far_centroids = centroids.clone();
+        far_centroids[ranked[K - 1]] = vec![2f64.powi(46); DIM];
+        for (centroids, expected) in [(&centroids, true), (&far_centroids, false)] {
+            let (_, _, _, _, satisfied) = vectordb::chip_ivf_query_multi_probe::<P>(
+                &query,
+                centroids,
+                &cluster_roots,
+                &clusters,
+                scheme,
+                "manhattan",
+            );
+            assert_eq!(satisfied, expected, "expected distances to be bounded by the sentinel");
+        }
+    }
+
+    #[test]
+    fn test_top_k_with_length() {
+        const K: usize = 2;
+        const DIM: usize = 3;
+        const CAPACITY: usize = 8;
+        let query = common::random_vector(DIM);
+        let vectors = common::random_vectors(DIM, 5);
+
+        // padding slots equal to the query would be the closest if they were included
+        let mut slots = vectors.clone();
+        slots.resize(CAPACITY, query.clone());
+
+        // the same circuit serves a growing database
+        for len in [3, 4, 5] {
+            let active = vectors[..len].to_vec();
+            let (indices, root, satisfied) =
+                vectordb::chip_top_k_with_length::<K>(&query, &slots, len);
+            assert!(satisfied, "expected constraints to be satisfied");
+            assert_eq!(
+                indices,
+                vectordb::top_k(&query, &active, K, &distances::euclidean_distance)
+            );
+
+            // the root binds the active length
+            let tree = vectordb::native_merkle_tree_with_capacity(
+                &vectordb::quantize_vectors(&active),
+                CAPACITY,
+                CommitmentScheme::DomainSeparated,
+            );
+            assert_eq!(root, tree.root());
+        }
+
+        // a length beyond the capacity should fail
+        let (_, _, satisfied) = vectordb::chip_top_k_with_length::<K>(&query, &slots, CAPACITY + 1);
+        assert!(!satisfied, "expected constraints to fail for a length beyond the capacity");
+
+        // fewer active vectors than results should fail
+        let (_, _, satisfied) = vectordb::chip_top_k_with_length::<K>(&query, &slots, 1);
+        assert!(!satisfied, "expected constraints to fail for fewer than K vectors");
+    }
+
+    #[test]
+    fn test_native_merkle() {
+        const DIM: usize = 3;
+
+        // both power-of-two and padded number of leaves
+        for n in 1..=9 {
+            let vectors = common::random_vectors(DIM, n);
+            assert_eq!(
+                vectordb::native_merkle(&vectors),
+                vectordb::chip_merkle(&vectors),
+                "native & chip roots differ for {} vectors",
+                n
+            );
+        }
+    }
+
+    #[test]
+    fn test_keccak_merkle() {
+        const DIM: usize = 3;
+
+        // both power-of-two and padded number of leaves, with negative values
+        for n in 1..=5 {
+            let vectors: Vec<Vec<f64>> = common::random_vectors(DIM, n)
+                .iter()
+                .map(|v| {
+                    v.iter().enumerate().map(|(i, x)| if i % 2 == 0 { -x } else { *x }).collect()
+                })
+                .collect();
+            let vectors = vectordb::quantize_vectors(&vectors);
+            let (root, satisfied) = vectordb::chip_keccak_merkle(&vectors);
+            assert!(satisfied, "expected constraints to be satisfied for {} vectors", n);
+            assert_eq!(
+                vectordb::native_keccak_merkle(&vectors),
+                root,
+                "native & chip roots differ for {} vectors",
+                n
+            );
+        }
+
+        // quantized values at the boundary of the valid range (-2^(2P+1), 2^(2P+1))
+        let bound = F::from(1u64 << 49) * F::from(1u64 << 48);
+        let vectors = vec![
+            vec![bound - F::one(), -(bound - F::one()), F::zero()],
+            vec![-(bound - F::one()), bound - F::one(), -F::one()],
+        ];
+        let (root, satisfied) = vectordb::chip_keccak_merkle(&vectors);
+        assert!(satisfied, "expected constraints to be satisfied at the boundary");
+        assert_eq!(vectordb::native_keccak_merkle(&vectors), root);
+
+        // values beyond the boundary have no valid encoding
+        let vectors = vec![vec![bound, F::zero(), F::zero()]];
+        let (_, satisfied) = vectordb::chip_keccak_merkle(&vectors);
+        assert!(!satisfied, "expected constraints to fail beyond the boundary");
+    }
+
+    #[test]
+    fn test_commitment_params() {
+        let ids = [
+            CommitmentParams::DEFAULT.id(),
+            CommitmentParams::BINARY.id(),
+            CommitmentParams::QUATERNARY.id(),
+        ];
+        assert!(ids[0] != ids[1] && ids[0] != ids[2] && ids[1] != ids[2]);
+
+        // the identifier changes with any parameter
+        let params = CommitmentParams::<3, 2>::new(8, 56, CommitmentScheme::Plain);
+        assert_ne!(params.id(), CommitmentParams::DEFAULT.id());
+    }
+
+    #[test]
+    fn test_merkle_scheme() {
+        const DIM: usize = 3;
+
+        for scheme in [CommitmentScheme::Plain, CommitmentScheme::DomainSeparated] {
+            for n in [1, 3, 4] {
+                let vectors = vectordb::quantize_vectors(&common::random_vectors(DIM, n));
+                assert_eq!(
+                    vectordb::native_merkle_tree(&vectors, scheme).root(),
+                    vectordb::chip_merkle_with_scheme(&vectors, scheme)
+                );
+            }
+        }
+
+        // a single "vector" made of two leaves is indistinguishable from the tree over
+        // those two leaves in the plain scheme, but not in the domain-separated one
+        let vectors = vectordb::quantize_vectors(&common::random_vectors(DIM, 2));
+        for (scheme, collides) in
+            [(CommitmentScheme::Plain, true), (CommitmentScheme::DomainSeparated, false)]
+        {
+            let tree = vectordb::native_merkle_tree(&vectors, scheme);
+            let forged =
+                vectordb::native_merkle_tree(&vec![vec![tree.leaf(0), tree.leaf(1)]], scheme);
+            assert_eq!(tree.root() == forged.root(), collides);
+        }
+    }
+
+    #[test]
+    fn test_merkle_path() {
+        const DIM: usize = 3;
+        let vectors = common::random_vectors(DIM, 5);
+
+        for index in 0..vectors.len() {
+            let (root, satisfied) = vectordb::chip_verify_merkle_path(&vectors, index, index);
+            assert_eq!(root, vectordb::chip_merkle(&vectors));
+            assert!(satisfied, "expected path of vector {} to be valid", index);
+        }
+
+        // path should not verify at another index
+        let (_, satisfied) = vectordb::chip_verify_merkle_path(&vectors, 1, 2);
+        assert!(!satisfied, "expected path to be invalid at another index");
+
+        // the domain-separated root binds the number of vectors
+        let scheme = CommitmentScheme::DomainSeparated;
+        assert!(vectordb::chip_verify_merkle_path_with_scheme(&vectors, scheme, 3, 5));
+        assert!(!vectordb::chip_verify_merkle_path_with_scheme(&vectors, scheme, 3, 8));
+    }
+
+    #[test]
+    fn test_merkle_arity() {
+        const DIM: usize = 3;
+
+        // 4-ary tree with a wider Poseidon
+        for n in [1, 3, 4, 6, 16, 17] {
+            let vectors = common::random_vectors(DIM, n);
+            for index in [0, n / 2, n - 1] {
+                let (native_root, chip_root, satisfied) = vectordb::chip_merkle_with_arity(
+                    &vectors,
+                    &CommitmentParams::QUATERNARY,
+                    index,
+                    index,
+                );
+                assert!(satisfied, "expected path of vector {} to be valid", index);
+                assert_eq!(native_root, chip_root, "native & chip roots differ for {} vectors", n);
+            }
+        }
+
+        // 2-ary tree is the same as before
+        let vectors = common::random_vectors(DIM, 5);
+        let (native_root, _, satisfied) =
+            vectordb::chip_merkle_with_arity(&vectors, &CommitmentParams::BINARY, 3, 3);
+        assert!(satisfied);
+        assert_eq!(
+            native_root,
+            vectordb::chip_merkle_with_scheme(
+                &vectordb::quantize_vectors(&vectors),
+                CommitmentScheme::DomainSeparated
+            )
+        );
+
+        // path should not verify at another index
+        let vectors = common::random_vectors(DIM, 6);
+        let (_, _, satisfied) =
+            vectordb::chip_merkle_with_arity(&vectors, &CommitmentParams::QUATERNARY, 1, 2);
+        assert!(!satisfied, "expected path to be invalid at another index");
+    }
+
+    #[test]
+    fn test_merkle_updates() {
+        const DIM: usize = 3;
+        let vectors = common::random_vectors(DIM, 5);
+        let updates = vec![
+            (MerkleUpdate::Insert, 5, common::random_vector(DIM)),
+            (MerkleUpdate::Replace, 1, common::random_vector(DIM)),
+            (MerkleUpdate::Delete, 2, vec![]),
+            (MerkleUpdate::Insert, 6, common::random_vector(DIM)),
+            (MerkleUpdate::Replace, 5, common::random_vector(DIM)),
+        ];
+
+        let (native_root, chip_root, satisfied) =
+            vectordb::chip_merkle_updates(&vectors, 8, &updates);
+        assert!(satisfied, "expected constraints to be satisfied");
+        assert_eq!(native_root, chip_root);
+
+        // the chained root should be the same as committing to the final database
+        let mut final_vectors = vectors.clone();
+        final_vectors.push(updates[4].2.clone());
+        final_vectors[1] = updates[1].2.clone();
+        final_vectors.push(updates[3].2.clone());
+        let mut tree = vectordb::native_merkle_tree(
+            &vectordb::quantize_vectors(&final_vectors),
+            CommitmentScheme::DomainSeparated,
+        );
+        tree.delete(2);
+        assert_eq!(tree.root(), chip_root);
+
+        // can't insert into an occupied slot
+        let updates = vec![(MerkleUpdate::Insert, 3, common::random_vector(DIM))];
+        let (_, _, satisfied) = vectordb::chip_merkle_updates(&vectors, 8, &updates);
+        assert!(!satisfied, "expected insertion into an occupied slot to fail");
+
+        // can't replace a deleted vector
+        let updates = vec![
+            (MerkleUpdate::Delete, 0, vec![]),
+            (MerkleUpdate::Replace, 0, common::random_vector(DIM)),
+        ];
+        let (_, _, satisfied) = vectordb::chip_merkle_updates(&vectors, 8, &updates);
+        assert!(!satisfied, "expected replacing a deleted vector to fail");
+    }
+
+    #[test]
+    fn test_merkle_append() {
+        const DIM: usize = 3;
+        const DEPTH: usize = 3;
+        let vectors = common::random_vectors(DIM, 3);
+        let batch = common::random_vectors(DIM, 3);
+
+        let (native_root, chip_root, satisfied) =
+            vectordb::chip_merkle_append(&vectors, &batch, DEPTH, false);
+        assert!(satisfied, "expected constraints to be satisfied");
+        assert_eq!(native_root, chip_root);
+
+        // should be the same as committing to all vectors at once with the same capacity
+        let all_vectors = vectordb::quantize_vectors(&[vectors.clone(), batch.clone()].concat());
+        let tree = vectordb::native_merkle_tree_with_capacity(
+            &all_vectors,
+            1 << DEPTH,
+            CommitmentScheme::DomainSeparated,
+        );
+        assert_eq!(tree.root(), native_root);
+
+        // six vectors are padded up to eight leaves, so it is also the same as committing to
+        // them without a capacity, which is not the case once the capacity is larger
+        let scheme = CommitmentScheme::DomainSeparated;
+        assert_eq!(vectordb::chip_merkle_with_scheme(&all_vectors, scheme), native_root);
+        let (larger_root, chip_root, satisfied) =
+            vectordb::chip_merkle_append(&vectors, &batch, DEPTH + 1, false);
+        assert!(satisfied, "expected constraints to be satisfied");
+        assert_eq!(larger_root, chip_root);
+        let tree =
+            vectordb::native_merkle_tree_with_capacity(&all_vectors, 1 << (DEPTH + 1), scheme);
+        assert_eq!(tree.root(), larger_root);
+        assert_ne!(vectordb::chip_merkle_with_scheme(&all_vectors, scheme), larger_root);
+
+        // a frontier that is not consistent with the old root should fail
+        let (_, _, satisfied) = vectordb::chip_merkle_append(&vectors, &batch, DEPTH, true);
+        assert!(!satisfied, "expected constraints to fail for a tampered frontier");
+    }
+
+    #[test]
+    fn test_sparse_merkle() {
+        const DIM: usize = 3;
+        const DEPTH: usize = 32;
+        let entries: Vec<(u64, Vec<f64>, Vec<u64>)> = [7, 1 << 20, 123456789]
+            .into_iter()
+            .map(|id| (id, common::random_vector(DIM), vec![id * 2, 42]))
+            .collect();
+        let query = common::random_vector(DIM);
+
+        let vectors: Vec<Vec<f64>> = entries.iter().map(|(_, v, _)| v.clone()).collect();
+        let (idx_native, _) =
+            vectordb::nearest_vector(&query, &vectors, &distances::euclidean_distance);
+
+        let (id_chip, satisfied) = vectordb::chip_sparse_nearest_vector(&entries, &query, DEPTH, 8);
+        assert!(satisfied, "expected constraints to be satisfied");
+        assert_eq!(id_chip, entries[idx_native].0);
+
+        // an id within the tree can't be proven absent
+        let (_, satisfied) = vectordb::chip_sparse_nearest_vector(&entries, &query, DEPTH, 7);
+        assert!(!satisfied, "expected non-inclusion of an existing id to fail");
+    }
+
+    #[test]
+    fn test_top_k_with_metadata() {
+        const K: usize = 3;
+        const DIM: usize = 3;
+        let query = common::random_vector(DIM);
+        let vectors = common::random_vectors(DIM, 6);
+        let metadata: Vec<Vec<u64>> = (0..6).map(|i| vec![1000 + i, i * i]).collect();
+
+        let indices_native = vectordb::top_k(&query, &vectors, K, &distances::euclidean_distance);
+        let (indices_chip, metadata_chip, root_chip, root_native, satisfied) =
+            vectordb::chip_top_k::<K>(&query, &vectors, &metadata, "euclidean");
+        assert!(satisfied, "expected constraints to be satisfied");
+        assert_eq!(indices_native, indices_chip);
+        for (index, metadata_chip) in indices_native.iter().zip(metadata_chip) {
+            assert_eq!(metadata[*index], metadata_chip);
+        }
+        assert_eq!(root_native, root_chip);
+
+        // the root binds the metadata
+        let mut other_metadata = metadata.clone();
+        other_metadata[2][0] += 1;
+        let (_, _, other_root, _, _) =
+            vectordb::chip_top_k::<K>(&query, &vectors, &other_metadata, "euclidean");
+        assert_ne!(root_chip, other_root);
+
+        // far vectors have distances beyond the sentinel, so the nearest one could tie with
+        // the masked result and be returned twice
+        let mut far_vectors = vectors.clone();
+        far_vectors[4] = vec![2f64.powi(46); DIM];
+        far_vectors[5] = vec![2f64.powi(46); DIM];
+        let (_, _, _, _, satisfied) =
+            vectordb::chip_top_k::<K>(&query, &far_vectors, &metadata, "manhattan");
+        assert!(!satisfied, "expected constraints to fail for distances beyond the sentinel");
+    }
+
+    #[test]
+    fn test_filtered_nearest_vector() {
+        const DIM: usize = 3;
+        let query = common::random_vector(DIM);
+        let vectors = common::random_vectors(DIM, 8);
+        // (tenant, date) for each vector
+        let attributes: Vec<Vec<u64>> = (0..8).map(|i| vec![i % 2, 20230100 + 10 * i]).collect();
+        let conditions = [(0, Comparison::Equal, 1), (1, Comparison::GreaterOrEqual, 20230125)];
+
+        // filter natively, then search
+        let matching: Vec<usize> =
+            (0..8).filter(|i| attributes[*i][0] == 1 && attributes[*i][1] >= 20230125).collect();
+        let matching_vectors: Vec<Vec<f64>> =
+            matching.iter().map(|i| vectors[*i].clone()).collect();
+        let (idx_native, _) =
+            vectordb::nearest_vector(&query, &matching_vectors, &distances::euclidean_distance);
+
+        let (idx_chip, satisfied) = vectordb::chip_filtered_nearest_vector(
+            &query,
+            &vectors,
+            &attributes,
+            &conditions,
+            "euclidean",
+        );
+        assert!(satisfied, "expected constraints to be satisfied");
+        assert_eq!(idx_chip, Some(matching[idx_native]));
+
+        // nothing matches
+        let conditions = [(0, Comparison::Equal, 1), (1, Comparison::LessThan, 20230100)];
+        let (idx_chip, satisfied) = vectordb::chip_filtered_nearest_vector(
+            &query,
+            &vectors,
+            &attributes,
+            &conditions,
+            "euclidean",
+        );
+        assert!(satisfied, "expected constraints to be satisfied");
+        assert_eq!(idx_chip, None);
+
+        // a distance beyond the sentinel could tie with the masked ones, so it is rejected
+        let mut far_vectors = vectors.clone();
+        far_vectors[0] = vec![2f64.powi(47); DIM];
+        let (_, satisfied) = vectordb::chip_filtered_nearest_vector(
+            &query,
+            &far_vectors,
+            &attributes,
+            &conditions,
+            "manhattan",
+        );
+        assert!(!satisfied, "expected constraints to fail for a distance beyond the sentinel");
+    }
+
+    #[test]
+    fn test_range_query() {
+        const DIM: usize = 3;
+        let vectors = common::random_vectors(DIM, 6);
+        // shares all but one element with the first vector, so that hamming distances differ
+        let mut query = vectors[0].clone();
+        query[DIM - 1] = common::random_vector(1)[0];
+
+        for (metric, distance) in [
+            ("euclidean", &distances::euclidean_distance as &dyn Fn(&Vec<f64>, &Vec<f64>) -> f64),
+            ("cosine", &distances::cosine_distance),
+            ("manhattan", &distances::manhattan_distance),
+            ("hamming", &distances::hamming_distance),
+        ] {
+            // threshold halfway between the closest two vectors
+            let mut sorted: Vec<f64> = vectors.iter().map(|v| distance(v, &query)).collect();
+            sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            let threshold = (sorted[0] + sorted[1]) / 2.0;
+
+            let members_native: Vec<bool> =
+                vectors.iter().map(|v| distance(v, &query) <= threshold).collect();
+            let (members_chip, count, commitment_matches, satisfied) =
+                vectordb::chip_range_query(&query, &vectors, threshold, metric);
+            assert!(satisfied, "expected constraints to be satisfied for {}", metric);
+            assert_eq!(members_native, members_chip, "memberships differ for {}", metric);
+            assert_eq!(count, members_native.iter().filter(|m| **m).count() as u64);
+            assert!(commitment_matches, "native & chip commitments differ for {}", metric);
+        }
+    }
+
+    #[test]
+    fn test_threshold_match() {
+        const DIM: usize = 3;
+        let query = common::random_vector(DIM);
+        let vectors = common::random_vectors(DIM, 5);
+
+        let (_, closest) =
+            vectordb::nearest_vector(&query, &vectors, &distances::euclidean_distance);
+        let min = distances::euclidean_distance(&closest, &query);
+
+        for scheme in [CommitmentScheme::Plain, CommitmentScheme::DomainSeparated] {
+            for (threshold, expected) in [(min * 1.1 + 0.01, true), (min * 0.9, false)] {
+                let (matched, _, roots_match, satisfied) =
+                    vectordb::chip_threshold_match(&query, &vectors, threshold, 1 << 100, scheme);
+                assert!(satisfied, "expected constraints to be satisfied");
+                assert!(roots_match, "native & chip roots differ");
+                assert_eq!(matched, expected, "expected match to be {} at {}", expected, threshold);
+            }
+        }
+
+        // even with the plain scheme, the query commitment is not the leaf of the same vector,
+        // and it changes with the salt
+        let (_, commitment, _, _) = vectordb::chip_threshold_match(
+            &closest,
+            &vectors,
+            1.0,
+            1 << 100,
+            CommitmentScheme::Plain,
+        );
+        let index = vectors.iter().position(|v| *v == closest).unwrap();
+        let leaf = vectordb::native_merkle_tree(
+            &vectordb::quantize_vectors(&vectors),
+            CommitmentScheme::Plain,
+        )
+        .leaf(index);
+        assert_ne!(commitment, leaf);
+        let (_, other_commitment, _, _) = vectordb::chip_threshold_match(
+            &closest,
+            &vectors,
+            1.0,
+            1 << 101,
+            CommitmentScheme::Plain,
+        );
+        assert_ne!(commitment, other_commitment);
+    }
+
+    #[test]
+    fn test_query_commitment() {
+        const DIM: usize = 3;
+        let query = common::random_vector(DIM);
+        let vectors = common::random_vectors(DIM, 4);
+
+        let (matches, commitment) = vectordb::chip_query_commitment(&query, &vectors, 1 << 100);
+        assert!(matches, "native & chip commitments differ");
+
+        // the salt blinds the query
+        let (_, other_commitment) = vectordb::chip_query_commitment(&query, &vectors, 1 << 101);
+        assert_ne!(commitment, other_commitment);
+
+        // a hashed result does not reveal whether it equals the query, for both schemes
+        let qquery = vectordb::quantize_vectors(&vec![query])[0].clone();
+        let salt = F::from(1 << 60);
+        for scheme in [CommitmentScheme::Plain, CommitmentScheme::DomainSeparated] {
+            let params = CommitmentParams { scheme, ..CommitmentParams::<3, 2>::DEFAULT };
+            assert_ne!(commit_query(&qquery, salt, &params), hash_result(&qquery, salt, &params));
+        }
+    }
+
+    #[test]
+    fn test_batch_nearest_vector() {
+        const DIM: usize = 3;
+        let queries = common::random_vectors(DIM, 3);
+        let vectors = common::random_vectors(DIM, 5);
+
+        let (indices_chip, root, satisfied) =
+            vectordb::chip_batch_nearest_vector(&queries, &vectors);
+        assert!(satisfied, "expected constraints to be satisfied");
+        for (query, idx_chip) in queries.iter().zip(indices_chip) {
+            let (idx_native, _) =
+                vectordb::nearest_vector(query, &vectors, &distances::cosine_distance);
+            assert_eq!(idx_native, idx_chip);
+        }
+
+        // the root is the same as a single query's
+        assert_eq!(root, vectordb::chip_merkle(&vectors));
+    }
+
     #[test]
     fn test_nearest_vector() {
         const DIM: usize = 4;